@@ -24,16 +24,30 @@ pub fn impl_for(realm: &QuickJsRealmAdapter) -> Result<(), JsError> {
         |_rt, realm, _this_obj, args| {
             //
             // convert vals to fetch options here, make fetch options Send
-            //arg0 = url: String
-            //arg1 = data: Object
-            //if arg0 is not a string the returned promise will reject
-            let url: Option<String> =
-                if !args.is_empty() && args[0].get_js_type() == JsValueType::String {
-                    Some(args[0].to_string()?)
+            //arg0 = url: String | Request
+            //arg1 = data: Object, overrides the members of a Request
+            //if arg0 is not a string or Request the returned promise will reject
+            let (url, fetch_init): (Option<String>, FetchInit) =
+                if !args.is_empty() && args[0].is_proxy_instance() {
+                    let p_data = realm.get_proxy_instance_info(&args[0])?;
+                    if !p_data.0.eq("Request") {
+                        return Err(JsError::new_str(
+                            "fetch expects a url or a Request as first arg",
+                        ));
+                    }
+                    let (url, mut fetch_init) = proxies::request_to_fetch(&p_data.1)?;
+                    if let Some(init) = args.get(1) {
+                        fetch_init.apply_js_object(realm, init)?;
+                    }
+                    (Some(url), fetch_init)
+                } else if !args.is_empty() && args[0].get_js_type() == JsValueType::String {
+                    (
+                        Some(args[0].to_string()?),
+                        FetchInit::from_js_object(realm, args.get(1))?,
+                    )
                 } else {
-                    None
+                    (None, FetchInit::from_js_object(realm, args.get(1))?)
                 };
-            let fetch_init: FetchInit = FetchInit::from_js_object(realm, args.get(1))?;
 
             realm.create_resolving_promise_async(
                 //
@@ -101,6 +115,107 @@ pub mod tests {
         }
     }
 
+    #[test]
+    fn test_headers_and_request() {
+        let rt = init_test_greco_rt();
+
+        let res = block_on(rt.eval(
+            None,
+            Script::new(
+                "test_headers_and_request.js",
+                r#"
+                (() => {
+                    let headers = new Headers({"Content-Type": "text/plain"});
+                    headers.append("X-Multi", "a");
+                    headers.append("x-multi", "b");
+                    headers.set("X-Single", "1");
+                    headers.set("x-single", "2");
+                    headers.append("X-Gone", "gone");
+                    headers.delete("x-gone");
+
+                    let names = [];
+                    for (const [name, value] of headers) {
+                        names.push(name + "=" + value);
+                    }
+
+                    let req = new Request("https://example.com/api", {method: "POST", headers, body: "hi"});
+                    let copy = req.clone();
+                    copy.headers.set("x-single", "3");
+
+                    return [
+                        headers.get("CONTENT-TYPE"),
+                        headers.has("X-GONE"),
+                        names.join(";"),
+                        req.url,
+                        req.method,
+                        req.headers.get("x-single"),
+                        copy.headers.get("x-single"),
+                        new Headers([["a", "1"], ["a", "2"]]).get("a"),
+                    ].join("|");
+                })()
+                "#,
+            ),
+        ))
+        .expect("script failed");
+
+        assert_eq!(
+            res.get_str(),
+            "text/plain|false|content-type=text/plain;x-multi=a, b;x-single=2|https://example.com/api|POST|2|3|1, 2"
+        );
+    }
+
+    #[test]
+    fn test_blob_and_form_data() {
+        let rt = init_test_greco_rt();
+
+        let res = block_on(rt.eval(
+            None,
+            Script::new(
+                "test_blob_and_form_data.js",
+                r#"
+                (async () => {
+                    let blob = new Blob(["hello ", new Uint8Array([119, 111, 114, 108, 100])], {type: "Text/Plain"});
+                    let slice = blob.slice(-5);
+                    let buffer = await blob.arrayBuffer();
+
+                    let form = new FormData();
+                    form.append("a", "1");
+                    form.append("a", "2");
+                    form.append("file", blob, "hello.txt");
+                    form.set("b", "3");
+                    let file = form.get("file");
+
+                    return [
+                        blob.size,
+                        blob.type,
+                        await slice.text(),
+                        buffer.byteLength,
+                        form.getAll("a").join(","),
+                        file.name,
+                        await file.text(),
+                        [...form.keys()].join(","),
+                    ].join("|");
+                })()
+                "#,
+            ),
+        ))
+        .expect("script failed");
+
+        let res = match res {
+            JsValueFacade::JsPromise { cached_promise } => {
+                block_on(cached_promise.get_promise_result())
+                    .expect("promise timed out")
+                    .expect("promise failed")
+            }
+            _ => panic!("result was not a promise"),
+        };
+
+        assert_eq!(
+            res.get_str(),
+            "11|text/plain|world|11|1,2|hello.txt|hello world|a,a,file,b"
+        );
+    }
+
     /*#[test]
     fn test_chart() {
         let rt = init_test_greco_rt();
@@ -1,18 +1,54 @@
-use crate::features::js_fetch::spec::Response;
+use crate::features::js_fetch::spec::{
+    Blob, FetchInit, FormData, FormDataValue, Headers, Method, Response,
+};
 use quickjs_runtime::jsutils::jsproxies::JsProxy;
 use quickjs_runtime::jsutils::JsError;
 use quickjs_runtime::quickjsrealmadapter::QuickJsRealmAdapter;
+use quickjs_runtime::quickjsvalueadapter::QuickJsValueAdapter;
 use std::cell::RefCell;
 use std::collections::HashMap;
+use std::rc::Rc;
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
 
 pub(crate) fn impl_for(realm: &QuickJsRealmAdapter) -> Result<(), JsError> {
+    impl_headers(realm)?;
+    impl_request(realm)?;
+    impl_blob(realm)?;
+    impl_form_data(realm)?;
     impl_response(realm)?;
     Ok(())
 }
 
+/// the headers of a Headers instance, shared with the Request it belongs to
+struct HeadersInstance {
+    headers: Rc<RefCell<Headers>>,
+    // the headers of a Response can not be changed
+    immutable: bool,
+}
+
+struct RequestInstance {
+    url: String,
+    // the headers of init are not used, those are in headers
+    init: FetchInit,
+    headers: Rc<RefCell<Headers>>,
+}
+
+impl RequestInstance {
+    fn to_fetch_init(&self) -> FetchInit {
+        let mut fetch_init = self.init.clone();
+        fetch_init.headers = self.headers.borrow().clone();
+        fetch_init
+    }
+}
+
 thread_local! {
     pub(crate) static RESPONSE_INSTANCES: RefCell<HashMap<usize, Arc<Response>>> = RefCell::new(HashMap::new());
+    static HEADERS_INSTANCES: RefCell<HashMap<usize, HeadersInstance>> = RefCell::new(HashMap::new());
+    static REQUEST_INSTANCES: RefCell<HashMap<usize, RequestInstance>> = RefCell::new(HashMap::new());
+    // Blob and File instances
+    static BLOB_INSTANCES: RefCell<HashMap<usize, Arc<Blob>>> = RefCell::new(HashMap::new());
+    static FORM_DATA_INSTANCES: RefCell<HashMap<usize, FormData>> = RefCell::new(HashMap::new());
 }
 
 fn with_response<C: FnOnce(&Arc<Response>) -> R, R>(id: &usize, consumer: C) -> Result<R, &str> {
@@ -26,6 +62,785 @@ fn with_response<C: FnOnce(&Arc<Response>) -> R, R>(id: &usize, consumer: C) ->
     })
 }
 
+fn with_headers<C: FnOnce(&HeadersInstance) -> R, R>(
+    id: &usize,
+    consumer: C,
+) -> Result<R, JsError> {
+    HEADERS_INSTANCES.with(|rc| {
+        let map = &*rc.borrow();
+        if let Some(headers) = map.get(id) {
+            Ok(consumer(headers))
+        } else {
+            Err(JsError::new_str("instance not found"))
+        }
+    })
+}
+
+fn with_request<C: FnOnce(&RequestInstance) -> R, R>(
+    id: &usize,
+    consumer: C,
+) -> Result<R, JsError> {
+    REQUEST_INSTANCES.with(|rc| {
+        let map = &*rc.borrow();
+        if let Some(request) = map.get(id) {
+            Ok(consumer(request))
+        } else {
+            Err(JsError::new_str("instance not found"))
+        }
+    })
+}
+
+/// the headers of a Headers instance which may be changed
+fn mutable_headers(id: &usize) -> Result<Rc<RefCell<Headers>>, JsError> {
+    with_headers(id, |instance| {
+        if instance.immutable {
+            Err(JsError::new_str("Headers are immutable"))
+        } else {
+            Ok(instance.headers.clone())
+        }
+    })?
+}
+
+/// the url and FetchInit of a Request instance, used for fetch(request)
+pub(crate) fn request_to_fetch(id: &usize) -> Result<(String, FetchInit), JsError> {
+    with_request(id, |request| (request.url.clone(), request.to_fetch_init()))
+}
+
+/// create Headers from a Headers instance, an array of [name, value] pairs or an object
+pub(crate) fn headers_from_js(
+    realm: &QuickJsRealmAdapter,
+    value: &QuickJsValueAdapter,
+) -> Result<Headers, JsError> {
+    let mut headers = Headers::new();
+    if value.is_null_or_undefined() {
+        return Ok(headers);
+    }
+    if value.is_proxy_instance() {
+        let p_data = realm.get_proxy_instance_info(value)?;
+        if p_data.0.eq("Headers") {
+            return with_headers(&p_data.1, |instance| instance.headers.borrow().clone());
+        }
+    }
+    if value.is_array() {
+        for x in 0..realm.get_array_length(value)? {
+            let pair = realm.get_array_element(value, x)?;
+            if !(pair.is_array() && realm.get_array_length(&pair)? == 2) {
+                return Err(JsError::new_str("headers should be [name, value] pairs"));
+            }
+            let name = realm.get_array_element(&pair, 0)?.to_string()?;
+            let val = realm.get_array_element(&pair, 1)?.to_string()?;
+            headers.append(name.as_str(), val.as_str())?;
+        }
+    } else {
+        realm.traverse_object_mut(value, |header_name, header_val| {
+            headers.append(header_name, header_val.to_string()?.as_str())
+        })?;
+    }
+    Ok(headers)
+}
+
+fn create_headers(
+    realm: &QuickJsRealmAdapter,
+    headers: Rc<RefCell<Headers>>,
+    immutable: bool,
+) -> Result<QuickJsValueAdapter, JsError> {
+    let inst_res = realm.instantiate_proxy(&[], "Headers", &[])?;
+    HEADERS_INSTANCES.with(|rc| {
+        let map = &mut *rc.borrow_mut();
+        map.insert(inst_res.0, HeadersInstance { headers, immutable })
+    });
+    Ok(inst_res.1)
+}
+
+/// an iterator like the one of Array.values() over items
+fn create_iterator(
+    realm: &QuickJsRealmAdapter,
+    items: Vec<QuickJsValueAdapter>,
+) -> Result<QuickJsValueAdapter, JsError> {
+    let array = realm.create_array()?;
+    for item in &items {
+        realm.push_array_element(&array, item)?;
+    }
+    let values_func = realm.get_object_property(&array, "values")?;
+    realm.invoke_function(Some(&array), &values_func, &[])
+}
+
+fn create_entries_iterator(
+    realm: &QuickJsRealmAdapter,
+    id: &usize,
+) -> Result<QuickJsValueAdapter, JsError> {
+    let entries = with_headers(id, |instance| instance.headers.borrow().entries())?;
+    let mut items = vec![];
+    for (name, value) in entries {
+        let pair = realm.create_array()?;
+        realm.push_array_element(&pair, &realm.create_string(name.as_str())?)?;
+        realm.push_array_element(&pair, &realm.create_string(value.as_str())?)?;
+        items.push(pair);
+    }
+    create_iterator(realm, items)
+}
+
+fn name_arg(args: &[QuickJsValueAdapter], method: &str) -> Result<String, JsError> {
+    if args.is_empty() || !args[0].is_string() {
+        return Err(JsError::new_string(format!(
+            "{method} expects a name (string) as first arg"
+        )));
+    }
+    args[0].to_string()
+}
+
+fn name_value_args(
+    args: &[QuickJsValueAdapter],
+    method: &str,
+) -> Result<(String, String), JsError> {
+    if args.len() < 2 || !args[0].is_string() {
+        return Err(JsError::new_string(format!(
+            "{method} expects 2 args (name: string, value: string)"
+        )));
+    }
+    Ok((args[0].to_string()?, args[1].to_string()?))
+}
+
+fn impl_headers(realm: &QuickJsRealmAdapter) -> Result<(), JsError> {
+    let headers_proxy = JsProxy::new()
+        .namespace(&[])
+        .name("Headers")
+        .constructor(|_rt, realm, id, args| {
+            let headers = match args.first() {
+                Some(init) => headers_from_js(realm, init)?,
+                None => Headers::new(),
+            };
+            HEADERS_INSTANCES.with(|rc| {
+                let map = &mut *rc.borrow_mut();
+                map.insert(
+                    id,
+                    HeadersInstance {
+                        headers: Rc::new(RefCell::new(headers)),
+                        immutable: false,
+                    },
+                )
+            });
+            Ok(())
+        })
+        .finalizer(|_rt, _realm, id| {
+            HEADERS_INSTANCES.with(|rc| {
+                let map = &mut *rc.borrow_mut();
+                map.remove(&id);
+            });
+        })
+        .method("append", |_rt, realm, instance_id, args| {
+            let (name, value) = name_value_args(args, "append")?;
+            let headers = mutable_headers(instance_id)?;
+            headers.borrow_mut().append(name.as_str(), value.as_str())?;
+            realm.create_undefined()
+        })
+        .method("set", |_rt, realm, instance_id, args| {
+            let (name, value) = name_value_args(args, "set")?;
+            let headers = mutable_headers(instance_id)?;
+            headers.borrow_mut().set(name.as_str(), value.as_str())?;
+            realm.create_undefined()
+        })
+        .method("delete", |_rt, realm, instance_id, args| {
+            let name = name_arg(args, "delete")?;
+            let headers = mutable_headers(instance_id)?;
+            headers.borrow_mut().delete(name.as_str());
+            realm.create_undefined()
+        })
+        .method("get", |_rt, realm, instance_id, args| {
+            let name = name_arg(args, "get")?;
+            let value = with_headers(instance_id, |instance| {
+                instance.headers.borrow().get_combined(name.as_str())
+            })?;
+            match value {
+                Some(value) => realm.create_string(value.as_str()),
+                None => realm.create_null(),
+            }
+        })
+        .method("has", |_rt, realm, instance_id, args| {
+            let name = name_arg(args, "has")?;
+            let has = with_headers(instance_id, |instance| {
+                instance.headers.borrow().has(name.as_str())
+            })?;
+            realm.create_boolean(has)
+        })
+        .method("getSetCookie", |_rt, realm, instance_id, _args| {
+            let cookies = with_headers(instance_id, |instance| {
+                instance
+                    .headers
+                    .borrow()
+                    .get("set-cookie")
+                    .cloned()
+                    .unwrap_or_default()
+            })?;
+            let array = realm.create_array()?;
+            for cookie in cookies {
+                realm.push_array_element(&array, &realm.create_string(cookie.as_str())?)?;
+            }
+            Ok(array)
+        })
+        .method("forEach", |_rt, realm, instance_id, args| {
+            if args.is_empty() || !args[0].is_function() {
+                return Err(JsError::new_str(
+                    "forEach expects a callback function as first arg",
+                ));
+            }
+            // iterate a copy so the callback may change the headers
+            let entries =
+                with_headers(instance_id, |instance| instance.headers.borrow().entries())?;
+            let this_arg = args.get(1);
+            for (name, value) in entries {
+                realm.invoke_function(
+                    this_arg,
+                    &args[0],
+                    &[
+                        &realm.create_string(value.as_str())?,
+                        &realm.create_string(name.as_str())?,
+                    ],
+                )?;
+            }
+            realm.create_undefined()
+        })
+        .method("entries", |_rt, realm, instance_id, _args| {
+            create_entries_iterator(realm, instance_id)
+        })
+        .method("Symbol.iterator", |_rt, realm, instance_id, _args| {
+            create_entries_iterator(realm, instance_id)
+        })
+        .method("keys", |_rt, realm, instance_id, _args| {
+            let entries =
+                with_headers(instance_id, |instance| instance.headers.borrow().entries())?;
+            let mut items = vec![];
+            for (name, _value) in entries {
+                items.push(realm.create_string(name.as_str())?);
+            }
+            create_iterator(realm, items)
+        })
+        .method("values", |_rt, realm, instance_id, _args| {
+            let entries =
+                with_headers(instance_id, |instance| instance.headers.borrow().entries())?;
+            let mut items = vec![];
+            for (_name, value) in entries {
+                items.push(realm.create_string(value.as_str())?);
+            }
+            create_iterator(realm, items)
+        });
+
+    realm.install_proxy(headers_proxy, true)?;
+
+    Ok(())
+}
+
+fn impl_request(realm: &QuickJsRealmAdapter) -> Result<(), JsError> {
+    let request_proxy = JsProxy::new()
+        .namespace(&[])
+        .name("Request")
+        .constructor(|_rt, realm, id, args| {
+            // new Request(input: string | Request, init?: object)
+            let (url, mut fetch_init) = match args.first() {
+                Some(input) if input.is_proxy_instance() => {
+                    let p_data = realm.get_proxy_instance_info(input)?;
+                    if !p_data.0.eq("Request") {
+                        return Err(JsError::new_str(
+                            "Request expects a url or a Request as first arg",
+                        ));
+                    }
+                    request_to_fetch(&p_data.1)?
+                }
+                Some(input) if input.is_string() => {
+                    let input = input.to_string()?;
+                    let url = url::Url::parse(input.as_str()).map_err(|e| {
+                        JsError::new_string(format!("Failed to parse URL from {input}: {e}"))
+                    })?;
+                    (url.to_string(), FetchInit::default())
+                }
+                _ => {
+                    return Err(JsError::new_str(
+                        "Request expects a url or a Request as first arg",
+                    ));
+                }
+            };
+            if let Some(init) = args.get(1) {
+                fetch_init.apply_js_object(realm, init)?;
+            }
+            if fetch_init.body.is_some() && matches!(fetch_init.method, Method::Get | Method::Head)
+            {
+                return Err(JsError::new_str(
+                    "Request with GET/HEAD method cannot have body",
+                ));
+            }
+            let headers = std::mem::take(&mut fetch_init.headers);
+            REQUEST_INSTANCES.with(|rc| {
+                let map = &mut *rc.borrow_mut();
+                map.insert(
+                    id,
+                    RequestInstance {
+                        url,
+                        init: fetch_init,
+                        headers: Rc::new(RefCell::new(headers)),
+                    },
+                )
+            });
+            Ok(())
+        })
+        .finalizer(|_rt, _realm, id| {
+            REQUEST_INSTANCES.with(|rc| {
+                let map = &mut *rc.borrow_mut();
+                map.remove(&id);
+            });
+        })
+        .getter("url", |_rt, realm, instance_id| {
+            let url = with_request(instance_id, |request| request.url.clone())?;
+            realm.create_string(url.as_str())
+        })
+        .getter("method", |_rt, realm, instance_id| {
+            let method = with_request(instance_id, |request| request.init.method.as_str())?;
+            realm.create_string(method)
+        })
+        .getter("mode", |_rt, realm, instance_id| {
+            let mode = with_request(instance_id, |request| request.init.mode.as_str())?;
+            realm.create_string(mode)
+        })
+        .getter("credentials", |_rt, realm, instance_id| {
+            let credentials =
+                with_request(instance_id, |request| request.init.credentials.as_str())?;
+            realm.create_string(credentials)
+        })
+        .getter("cache", |_rt, realm, instance_id| {
+            let cache = with_request(instance_id, |request| request.init.cache.as_str())?;
+            realm.create_string(cache)
+        })
+        .getter("redirect", |_rt, realm, instance_id| {
+            let redirect = with_request(instance_id, |request| request.init.redirect.as_str())?;
+            realm.create_string(redirect)
+        })
+        .getter("headers", |_rt, realm, instance_id| {
+            // changes to the returned Headers change the headers of the Request
+            let headers = with_request(instance_id, |request| request.headers.clone())?;
+            create_headers(realm, headers, false)
+        })
+        .method("clone", |_rt, realm, instance_id, _args| {
+            let (url, mut fetch_init) = request_to_fetch(instance_id)?;
+            let headers = std::mem::take(&mut fetch_init.headers);
+            let inst_res =
+                realm.instantiate_proxy(&[], "Request", &[realm.create_string(url.as_str())?])?;
+            REQUEST_INSTANCES.with(|rc| {
+                let map = &mut *rc.borrow_mut();
+                map.insert(
+                    inst_res.0,
+                    RequestInstance {
+                        url,
+                        init: fetch_init,
+                        headers: Rc::new(RefCell::new(headers)),
+                    },
+                )
+            });
+            Ok(inst_res.1)
+        });
+
+    realm.install_proxy(request_proxy, true)?;
+
+    Ok(())
+}
+
+fn with_blob<C: FnOnce(&Arc<Blob>) -> R, R>(id: &usize, consumer: C) -> Result<R, JsError> {
+    BLOB_INSTANCES.with(|rc| {
+        let map = &*rc.borrow();
+        if let Some(blob) = map.get(id) {
+            Ok(consumer(blob))
+        } else {
+            Err(JsError::new_str("instance not found"))
+        }
+    })
+}
+
+fn with_form_data<C: FnOnce(&mut FormData) -> R, R>(id: &usize, consumer: C) -> Result<R, JsError> {
+    FORM_DATA_INSTANCES.with(|rc| {
+        let map = &mut *rc.borrow_mut();
+        if let Some(form_data) = map.get_mut(id) {
+            Ok(consumer(form_data))
+        } else {
+            Err(JsError::new_str("instance not found"))
+        }
+    })
+}
+
+/// the Blob of a Blob or File instance, None if value is not a Blob
+fn get_blob(
+    realm: &QuickJsRealmAdapter,
+    value: &QuickJsValueAdapter,
+) -> Result<Option<Arc<Blob>>, JsError> {
+    if value.is_proxy_instance() {
+        let p_data = realm.get_proxy_instance_info(value)?;
+        if p_data.0.eq("Blob") || p_data.0.eq("File") {
+            return with_blob(&p_data.1, |blob| Some(blob.clone()));
+        }
+    }
+    Ok(None)
+}
+
+/// create a Blob from an array of strings, typed arrays and Blobs and an options object ({type})
+fn blob_from_js(
+    realm: &QuickJsRealmAdapter,
+    parts: Option<&QuickJsValueAdapter>,
+    options: Option<&QuickJsValueAdapter>,
+    name: Option<String>,
+) -> Result<Blob, JsError> {
+    let mut bytes = vec![];
+    if let Some(parts) = parts.filter(|p| !p.is_null_or_undefined()) {
+        if !parts.is_array() {
+            return Err(JsError::new_str("Blob parts should be an array"));
+        }
+        for x in 0..realm.get_array_length(parts)? {
+            let part = realm.get_array_element(parts, x)?;
+            if part.is_typed_array() {
+                bytes.extend(realm.copy_typed_array_buffer(&part)?);
+            } else if let Some(blob) = get_blob(realm, &part)? {
+                bytes.extend_from_slice(blob.bytes.as_slice());
+            } else {
+                bytes.extend_from_slice(part.to_string()?.as_bytes());
+            }
+        }
+    }
+    let mut content_type = String::new();
+    if let Some(options) = options.filter(|o| o.is_object() && !o.is_null()) {
+        let type_opt = realm.get_object_property(options, "type")?;
+        if !type_opt.is_null_or_undefined() {
+            content_type = type_opt.to_string()?.to_ascii_lowercase();
+        }
+    }
+    Ok(Blob {
+        bytes,
+        content_type,
+        name,
+    })
+}
+
+/// create a Blob instance, or a File instance if the blob has a name
+pub(crate) fn create_blob(
+    realm: &QuickJsRealmAdapter,
+    blob: Blob,
+) -> Result<QuickJsValueAdapter, JsError> {
+    let inst_res = match blob.name.as_ref() {
+        Some(name) => realm.instantiate_proxy(
+            &[],
+            "File",
+            &[realm.create_array()?, realm.create_string(name.as_str())?],
+        )?,
+        None => realm.instantiate_proxy(&[], "Blob", &[])?,
+    };
+    BLOB_INSTANCES.with(|rc| {
+        let map = &mut *rc.borrow_mut();
+        map.insert(inst_res.0, Arc::new(blob))
+    });
+    Ok(inst_res.1)
+}
+
+fn store_blob(id: usize, blob: Blob) {
+    BLOB_INSTANCES.with(|rc| {
+        let map = &mut *rc.borrow_mut();
+        map.insert(id, Arc::new(blob))
+    });
+}
+
+/// resolve a relative index of Blob.slice() against len
+fn slice_index(
+    arg: Option<&QuickJsValueAdapter>,
+    default: usize,
+    len: usize,
+) -> Result<usize, JsError> {
+    let arg = match arg.filter(|a| !a.is_null_or_undefined()) {
+        Some(arg) => arg,
+        None => return Ok(default),
+    };
+    let idx = if arg.is_i32() {
+        arg.to_i32() as i64
+    } else if arg.is_f64() {
+        arg.to_f64() as i64
+    } else {
+        return Err(JsError::new_str("slice expects numbers as start and end"));
+    };
+    Ok(if idx < 0 {
+        (len as i64 + idx).max(0) as usize
+    } else {
+        (idx as usize).min(len)
+    })
+}
+
+/// the methods which Blob and File share
+fn blob_proxy(name: &'static str) -> JsProxy {
+    JsProxy::new()
+        .namespace(&[])
+        .name(name)
+        .finalizer(|_rt, _realm, id| {
+            BLOB_INSTANCES.with(|rc| {
+                let map = &mut *rc.borrow_mut();
+                map.remove(&id);
+            });
+        })
+        .getter("size", |_rt, realm, instance_id| {
+            let size = with_blob(instance_id, |blob| blob.bytes.len())?;
+            realm.create_f64(size as f64)
+        })
+        .getter("type", |_rt, realm, instance_id| {
+            let content_type = with_blob(instance_id, |blob| blob.content_type.clone())?;
+            realm.create_string(content_type.as_str())
+        })
+        .method("text", |_rt, realm, instance_id, _args| {
+            let blob = with_blob(instance_id, |blob| blob.clone())?;
+            realm.create_resolving_promise_async(
+                async move { Ok(String::from_utf8_lossy(blob.bytes.as_slice()).into_owned()) },
+                |realm, res| realm.create_string(res.as_str()),
+            )
+        })
+        .method("bytes", |_rt, realm, instance_id, _args| {
+            let blob = with_blob(instance_id, |blob| blob.clone())?;
+            realm.create_resolving_promise_async(
+                async move { Ok(blob.bytes.clone()) },
+                |realm, res| realm.create_typed_array_uint8(res),
+            )
+        })
+        .method("arrayBuffer", |_rt, realm, instance_id, _args| {
+            let blob = with_blob(instance_id, |blob| blob.clone())?;
+            realm.create_resolving_promise_async(
+                async move { Ok(blob.bytes.clone()) },
+                |realm, res| {
+                    let array = realm.create_typed_array_uint8(res)?;
+                    realm.get_object_property(&array, "buffer")
+                },
+            )
+        })
+        .method("slice", |_rt, realm, instance_id, args| {
+            // slice(start?: number, end?: number, contentType?: string): Blob
+            let blob = with_blob(instance_id, |blob| blob.clone())?;
+            let len = blob.bytes.len();
+            let start = slice_index(args.first(), 0, len)?;
+            let end = slice_index(args.get(1), len, len)?.max(start);
+            let content_type = match args.get(2).filter(|a| !a.is_null_or_undefined()) {
+                Some(content_type) => content_type.to_string()?.to_ascii_lowercase(),
+                None => String::new(),
+            };
+            create_blob(
+                realm,
+                Blob {
+                    bytes: blob.bytes[start..end].to_vec(),
+                    content_type,
+                    name: None,
+                },
+            )
+        })
+}
+
+fn impl_blob(realm: &QuickJsRealmAdapter) -> Result<(), JsError> {
+    // new Blob(parts?: Array<string | TypedArray | Blob>, options?: {type?: string})
+    let blob_proxy = blob_proxy("Blob").constructor(|_rt, realm, id, args| {
+        let blob = blob_from_js(realm, args.first(), args.get(1), None)?;
+        store_blob(id, blob);
+        Ok(())
+    });
+    realm.install_proxy(blob_proxy, true)?;
+
+    // new File(parts: Array<string | TypedArray | Blob>, name: string, options?: {type?: string})
+    let file_proxy = blob_proxy("File")
+        .constructor(|_rt, realm, id, args| {
+            if args.len() < 2 || !args[1].is_string() {
+                return Err(JsError::new_str(
+                    "File requires 2 or 3 args (parts: Array, name: string, options?: {type?: string})",
+                ));
+            }
+            let name = args[1].to_string()?;
+            let blob = blob_from_js(realm, args.first(), args.get(2), Some(name))?;
+            store_blob(id, blob);
+            Ok(())
+        })
+        .getter("name", |_rt, realm, instance_id| {
+            let name = with_blob(instance_id, |blob| blob.name.clone().unwrap_or_default())?;
+            realm.create_string(name.as_str())
+        });
+    realm.install_proxy(file_proxy, true)?;
+
+    Ok(())
+}
+
+/// create a FormData instance
+pub(crate) fn create_form_data(
+    realm: &QuickJsRealmAdapter,
+    form_data: FormData,
+) -> Result<QuickJsValueAdapter, JsError> {
+    let inst_res = realm.instantiate_proxy(&[], "FormData", &[])?;
+    FORM_DATA_INSTANCES.with(|rc| {
+        let map = &mut *rc.borrow_mut();
+        map.insert(inst_res.0, form_data)
+    });
+    Ok(inst_res.1)
+}
+
+/// the name and value args of FormData.append and FormData.set, a Blob value becomes a File
+fn form_data_args(
+    realm: &QuickJsRealmAdapter,
+    args: &[QuickJsValueAdapter],
+    method: &str,
+) -> Result<(String, FormDataValue), JsError> {
+    if args.len() < 2 || !args[0].is_string() {
+        return Err(JsError::new_string(format!(
+            "{method} requires 2 or 3 args (name: string, value: string | Blob, fileName?: string)"
+        )));
+    }
+    let name = args[0].to_string()?;
+    let value = match get_blob(realm, &args[1])? {
+        Some(blob) => {
+            let file_name = match args.get(2).filter(|a| !a.is_null_or_undefined()) {
+                Some(file_name) => file_name.to_string()?,
+                None => blob.name.clone().unwrap_or_else(|| "blob".to_string()),
+            };
+            FormDataValue::File(Blob {
+                bytes: blob.bytes.clone(),
+                content_type: blob.content_type.clone(),
+                name: Some(file_name),
+            })
+        }
+        None => FormDataValue::Text(args[1].to_string()?),
+    };
+    Ok((name, value))
+}
+
+fn form_data_value_to_js(
+    realm: &QuickJsRealmAdapter,
+    value: FormDataValue,
+) -> Result<QuickJsValueAdapter, JsError> {
+    match value {
+        FormDataValue::Text(text) => realm.create_string(text.as_str()),
+        FormDataValue::File(blob) => create_blob(realm, blob),
+    }
+}
+
+/// a copy of the entries, File values are not created while the FormData is borrowed
+fn form_data_entries(id: &usize) -> Result<Vec<(String, FormDataValue)>, JsError> {
+    with_form_data(id, |form_data| form_data.entries().to_vec())
+}
+
+fn impl_form_data(realm: &QuickJsRealmAdapter) -> Result<(), JsError> {
+    let form_data_proxy = JsProxy::new()
+        .namespace(&[])
+        .name("FormData")
+        .constructor(|_rt, _realm, id, _args| {
+            FORM_DATA_INSTANCES.with(|rc| {
+                let map = &mut *rc.borrow_mut();
+                map.insert(id, FormData::new())
+            });
+            Ok(())
+        })
+        .finalizer(|_rt, _realm, id| {
+            FORM_DATA_INSTANCES.with(|rc| {
+                let map = &mut *rc.borrow_mut();
+                map.remove(&id);
+            });
+        })
+        .method("append", |_rt, realm, instance_id, args| {
+            let (name, value) = form_data_args(realm, args, "append")?;
+            with_form_data(instance_id, |form_data| {
+                form_data.append(name.as_str(), value)
+            })?;
+            realm.create_undefined()
+        })
+        .method("set", |_rt, realm, instance_id, args| {
+            let (name, value) = form_data_args(realm, args, "set")?;
+            with_form_data(instance_id, |form_data| form_data.set(name.as_str(), value))?;
+            realm.create_undefined()
+        })
+        .method("delete", |_rt, realm, instance_id, args| {
+            let name = name_arg(args, "delete")?;
+            with_form_data(instance_id, |form_data| form_data.delete(name.as_str()))?;
+            realm.create_undefined()
+        })
+        .method("has", |_rt, realm, instance_id, args| {
+            let name = name_arg(args, "has")?;
+            let has = with_form_data(instance_id, |form_data| form_data.has(name.as_str()))?;
+            realm.create_boolean(has)
+        })
+        .method("get", |_rt, realm, instance_id, args| {
+            let name = name_arg(args, "get")?;
+            let value = with_form_data(instance_id, |form_data| {
+                form_data.get(name.as_str()).cloned()
+            })?;
+            match value {
+                Some(value) => form_data_value_to_js(realm, value),
+                None => realm.create_null(),
+            }
+        })
+        .method("getAll", |_rt, realm, instance_id, args| {
+            let name = name_arg(args, "getAll")?;
+            let values = with_form_data(instance_id, |form_data| {
+                form_data
+                    .get_all(name.as_str())
+                    .into_iter()
+                    .cloned()
+                    .collect::<Vec<FormDataValue>>()
+            })?;
+            let array = realm.create_array()?;
+            for value in values {
+                realm.push_array_element(&array, &form_data_value_to_js(realm, value)?)?;
+            }
+            Ok(array)
+        })
+        .method("forEach", |_rt, realm, instance_id, args| {
+            if args.is_empty() || !args[0].is_function() {
+                return Err(JsError::new_str(
+                    "forEach expects a callback function as first arg",
+                ));
+            }
+            let this_arg = args.get(1);
+            for (name, value) in form_data_entries(instance_id)? {
+                realm.invoke_function(
+                    this_arg,
+                    &args[0],
+                    &[
+                        &form_data_value_to_js(realm, value)?,
+                        &realm.create_string(name.as_str())?,
+                    ],
+                )?;
+            }
+            realm.create_undefined()
+        })
+        .method("entries", |_rt, realm, instance_id, _args| {
+            create_form_data_entries_iterator(realm, instance_id)
+        })
+        .method("Symbol.iterator", |_rt, realm, instance_id, _args| {
+            create_form_data_entries_iterator(realm, instance_id)
+        })
+        .method("keys", |_rt, realm, instance_id, _args| {
+            let mut items = vec![];
+            for (name, _value) in form_data_entries(instance_id)? {
+                items.push(realm.create_string(name.as_str())?);
+            }
+            create_iterator(realm, items)
+        })
+        .method("values", |_rt, realm, instance_id, _args| {
+            let mut items = vec![];
+            for (_name, value) in form_data_entries(instance_id)? {
+                items.push(form_data_value_to_js(realm, value)?);
+            }
+            create_iterator(realm, items)
+        });
+
+    realm.install_proxy(form_data_proxy, true)?;
+
+    Ok(())
+}
+
+fn create_form_data_entries_iterator(
+    realm: &QuickJsRealmAdapter,
+    id: &usize,
+) -> Result<QuickJsValueAdapter, JsError> {
+    let mut items = vec![];
+    for (name, value) in form_data_entries(id)? {
+        let pair = realm.create_array()?;
+        realm.push_array_element(&pair, &realm.create_string(name.as_str())?)?;
+        realm.push_array_element(&pair, &form_data_value_to_js(realm, value)?)?;
+        items.push(pair);
+    }
+    create_iterator(realm, items)
+}
+
 fn impl_response(realm: &QuickJsRealmAdapter) -> Result<(), JsError> {
     let response_proxy = JsProxy::new()
         .namespace(&[])
@@ -53,13 +868,24 @@ fn impl_response(realm: &QuickJsRealmAdapter) -> Result<(), JsError> {
             // todo with_response is impld sucky
             .unwrap()
         })
+        .getter("bodyUsed", |_rt, realm, instance_id| {
+            let body_used = with_response(instance_id, |response| {
+                response.body_used.load(Ordering::SeqCst)
+            })
+            .map_err(JsError::new_str)?;
+            realm.create_boolean(body_used)
+        })
         .method("text", |_rt, realm, instance_id, _args| {
             //
             let response = with_response(instance_id, |response| response.clone())
                 .map_err(JsError::new_str)?;
-            // todo promise may seem futile now but later we will just store bytes in body and encode to string async
+            // the body is marked as used right away, a second call rejects
+            let body_use = response.use_body();
             realm.create_resolving_promise_async(
-                async move { response.text().await },
+                async move {
+                    body_use?;
+                    response.text().await
+                },
                 // todo js_string_crea2 with String
                 |realm, res| realm.create_string(res.as_str()),
             )
@@ -68,26 +894,77 @@ fn impl_response(realm: &QuickJsRealmAdapter) -> Result<(), JsError> {
             //
             let response = with_response(instance_id, |response| response.clone())
                 .map_err(JsError::new_str)?;
-            // todo promise may seem futile now but later we will just store bytes in body and encode to string async
+            let body_use = response.use_body();
             realm.create_resolving_promise_async(
-                async move { response.text().await },
+                async move {
+                    body_use?;
+                    response.text().await
+                },
                 // todo js_string_crea2 with String
                 |realm, res| realm.json_parse(res.as_str()),
             )
         })
-        // non std util method, need to impl readablestream and such later
         .method("bytes", |_rt, realm, instance_id, _args| {
             //
             let response = with_response(instance_id, |response| response.clone())
                 .map_err(JsError::new_str)?;
-            // todo promise may seem futile now but later we will just store bytes in body and encode to string async
+            let body_use = response.use_body();
             realm.create_resolving_promise_async(
-                async move { response.bytes().await },
-                // todo js_string_crea2 with String
+                async move {
+                    body_use?;
+                    response.bytes().await
+                },
                 |realm, res| realm.create_typed_array_uint8(res),
             )
         })
-        // non std header, todo create Headers proxy
+        .method("arrayBuffer", |_rt, realm, instance_id, _args| {
+            //
+            let response = with_response(instance_id, |response| response.clone())
+                .map_err(JsError::new_str)?;
+            let body_use = response.use_body();
+            realm.create_resolving_promise_async(
+                async move {
+                    body_use?;
+                    response.bytes().await
+                },
+                |realm, res| {
+                    let array = realm.create_typed_array_uint8(res)?;
+                    realm.get_object_property(&array, "buffer")
+                },
+            )
+        })
+        .method("blob", |_rt, realm, instance_id, _args| {
+            //
+            let response = with_response(instance_id, |response| response.clone())
+                .map_err(JsError::new_str)?;
+            let body_use = response.use_body();
+            realm.create_resolving_promise_async(
+                async move {
+                    body_use?;
+                    response.blob().await
+                },
+                |realm, res| create_blob(realm, res),
+            )
+        })
+        .method("formData", |_rt, realm, instance_id, _args| {
+            //
+            let response = with_response(instance_id, |response| response.clone())
+                .map_err(JsError::new_str)?;
+            let body_use = response.use_body();
+            realm.create_resolving_promise_async(
+                async move {
+                    body_use?;
+                    response.form_data().await
+                },
+                |realm, res| create_form_data(realm, res),
+            )
+        })
+        .getter("headers", |_rt, realm, instance_id| {
+            let headers = with_response(instance_id, |response| response.headers.clone())
+                .map_err(JsError::new_str)?;
+            create_headers(realm, Rc::new(RefCell::new(headers)), true)
+        })
+        // non std, use headers.get(name)
         .method("getHeader", |_rt, realm, instance_id, args| {
             //
             let response = with_response(instance_id, |response| response.clone())
@@ -3,15 +3,17 @@
 //!
 //!
 
-use crate::features::js_fetch::proxies::RESPONSE_INSTANCES;
+use crate::features::js_fetch::proxies::{headers_from_js, RESPONSE_INSTANCES};
 use quickjs_runtime::jsutils::JsError;
 use quickjs_runtime::quickjsrealmadapter::QuickJsRealmAdapter;
 use quickjs_runtime::quickjsvalueadapter::QuickJsValueAdapter;
 use std::collections::HashMap;
 use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 
 // todo see stackoverflow.com/questions/44121783
+#[derive(Clone, Copy)]
 pub enum Mode {
     Cors,
     NoCors,
@@ -41,6 +43,7 @@ impl FromStr for Mode {
     }
 }
 
+#[derive(Clone, Copy)]
 pub enum Method {
     Get,
     Head,
@@ -110,6 +113,7 @@ impl FromStr for Method {
     }
 }
 
+#[derive(Clone, Copy)]
 pub enum Redirect {
     Follow,
     Manual,
@@ -139,6 +143,7 @@ impl FromStr for Redirect {
     }
 }
 
+#[derive(Clone, Copy)]
 pub enum Credentials {
     Omit,
     SameOrigin,
@@ -168,6 +173,7 @@ impl FromStr for Credentials {
     }
 }
 
+#[derive(Clone, Copy)]
 pub enum Cache {
     Default,
     NoStore,
@@ -206,97 +212,111 @@ impl FromStr for Cache {
     }
 }
 
+#[derive(Clone)]
 pub struct FetchInit {
-    method: Method,
-    headers: Headers,
-    body: Option<Body>,
-    mode: Mode,
-    credentials: Credentials,
-    cache: Cache,
-    redirect: Redirect,
+    pub(crate) method: Method,
+    pub(crate) headers: Headers,
+    pub(crate) body: Option<Body>,
+    pub(crate) mode: Mode,
+    pub(crate) credentials: Credentials,
+    pub(crate) cache: Cache,
+    pub(crate) redirect: Redirect,
 }
 impl FetchInit {
     pub fn from_js_object(
         realm: &QuickJsRealmAdapter,
         value: Option<&QuickJsValueAdapter>,
     ) -> Result<Self, JsError> {
-        let mut fetch_init = Self {
-            method: Method::Get,
-            headers: Headers::new(),
-            body: None,
-            mode: Mode::NoCors,
-            credentials: Credentials::SameOrigin,
-            cache: Cache::Default,
-            redirect: Redirect::Follow,
-        };
-
+        let mut fetch_init = Self::default();
         if let Some(init_obj) = value {
-            realm.traverse_object_mut(init_obj, |prop_name, prop| {
-                //
+            fetch_init.apply_js_object(realm, init_obj)?;
+        }
+        Ok(fetch_init)
+    }
+    /// overwrite the members which are set in init_obj, used for fetch(request, init) and new Request(request, init)
+    pub fn apply_js_object(
+        &mut self,
+        realm: &QuickJsRealmAdapter,
+        init_obj: &QuickJsValueAdapter,
+    ) -> Result<(), JsError> {
+        if init_obj.is_null_or_undefined() {
+            return Ok(());
+        }
+        realm.traverse_object_mut(init_obj, |prop_name, prop| {
+            //
+
+            match prop_name {
+                "method" => {
+                    let val = prop.to_string()?;
+                    self.method = Method::from_str(val.as_str())
+                        .map_err(|_e| JsError::new_str("No such method"))?;
+                }
+                "mode" => {
+                    let val = prop.to_string()?;
+                    self.mode = Mode::from_str(val.as_str())
+                        .map_err(|_e| JsError::new_str("No such mode"))?;
+                }
+                "cache" => {
+                    let val = prop.to_string()?;
+                    self.cache = Cache::from_str(val.as_str())
+                        .map_err(|_e| JsError::new_str("No such cache"))?;
+                }
+                "credentials" => {
+                    let val = prop.to_string()?;
+                    self.credentials = Credentials::from_str(val.as_str())
+                        .map_err(|_e| JsError::new_str("No such credentials"))?;
+                }
 
-                match prop_name {
-                    "method" => {
-                        let val = prop.to_string()?;
-                        fetch_init.method = Method::from_str(val.as_str())
-                            .map_err(|_e| JsError::new_str("No such method"))?;
-                    }
-                    "mode" => {
-                        let val = prop.to_string()?;
-                        fetch_init.mode = Mode::from_str(val.as_str())
-                            .map_err(|_e| JsError::new_str("No such mode"))?;
-                    }
-                    "cache" => {
-                        let val = prop.to_string()?;
-                        fetch_init.cache = Cache::from_str(val.as_str())
-                            .map_err(|_e| JsError::new_str("No such cache"))?;
-                    }
-                    "credentials" => {
-                        let val = prop.to_string()?;
-                        fetch_init.credentials = Credentials::from_str(val.as_str())
-                            .map_err(|_e| JsError::new_str("No such credentials"))?;
-                    }
+                "redirect" => {
+                    let val = prop.to_string()?;
+                    self.redirect = Redirect::from_str(val.as_str())
+                        .map_err(|_e| JsError::new_str("No such redirect"))?;
+                }
 
-                    "redirect" => {
+                "body" => {
+                    if prop.is_string() {
                         let val = prop.to_string()?;
-                        fetch_init.redirect = Redirect::from_str(val.as_str())
-                            .map_err(|_e| JsError::new_str("No such redirect"))?;
+                        self.body = Some(Body {
+                            text: Some(val),
+                            bytes: None,
+                        });
                     }
-
-                    "body" => {
-                        if prop.is_string() {
-                            let val = prop.to_string()?;
-                            fetch_init.body = Some(Body {
-                                text: Some(val),
-                                bytes: None,
-                            });
-                        }
-                        if prop.is_typed_array() {
-                            let val = realm.copy_typed_array_buffer(prop)?;
-                            fetch_init.body = Some(Body {
-                                bytes: Some(val),
-                                text: None,
-                            });
-                        }
-                    }
-                    "headers" => {
-                        realm.traverse_object_mut(prop, |header_name, header_val| {
-                            fetch_init
-                                .headers
-                                .append(header_name, header_val.to_string()?.as_str());
-                            Ok(())
-                        })?;
+                    if prop.is_typed_array() {
+                        let val = realm.copy_typed_array_buffer(prop)?;
+                        self.body = Some(Body {
+                            bytes: Some(val),
+                            text: None,
+                        });
                     }
-
-                    _ => {}
+                }
+                "headers" => {
+                    // headers of the init replace the headers of a Request
+                    self.headers = headers_from_js(realm, prop)?;
                 }
 
-                Ok(())
-            })?;
+                _ => {}
+            }
+
+            Ok(())
+        })
+    }
+}
+impl Default for FetchInit {
+    fn default() -> Self {
+        Self {
+            method: Method::Get,
+            headers: Headers::new(),
+            body: None,
+            mode: Mode::NoCors,
+            credentials: Credentials::SameOrigin,
+            cache: Cache::Default,
+            redirect: Redirect::Follow,
         }
-        Ok(fetch_init)
     }
 }
 
+/// header names are case-insensitive, they are stored in lowercase
+#[derive(Clone)]
 pub struct Headers {
     map: HashMap<String, Vec<String>>,
 }
@@ -306,15 +326,61 @@ impl Headers {
             map: Default::default(),
         }
     }
-    pub fn append(&mut self, name: &str, value: &str) {
-        if !self.map.contains_key(name) {
-            self.map.insert(name.to_string(), vec![]);
-        }
-        let vec = self.map.get_mut(name).unwrap();
-        vec.push(value.to_string());
+    /// check if name and value are a valid header name and value
+    pub fn validate(name: &str, value: &str) -> Result<(), JsError> {
+        reqwest::header::HeaderName::from_bytes(name.as_bytes())
+            .map_err(|_e| JsError::new_string(format!("invalid header name: {name}")))?;
+        reqwest::header::HeaderValue::from_str(value)
+            .map_err(|_e| JsError::new_string(format!("invalid value for header {name}")))?;
+        Ok(())
+    }
+    pub fn append(&mut self, name: &str, value: &str) -> Result<(), JsError> {
+        let value = value.trim();
+        Self::validate(name, value)?;
+        self.map
+            .entry(name.to_ascii_lowercase())
+            .or_default()
+            .push(value.to_string());
+        Ok(())
+    }
+    /// replace all values of a header
+    pub fn set(&mut self, name: &str, value: &str) -> Result<(), JsError> {
+        let value = value.trim();
+        Self::validate(name, value)?;
+        self.map
+            .insert(name.to_ascii_lowercase(), vec![value.to_string()]);
+        Ok(())
     }
+    pub fn delete(&mut self, name: &str) {
+        self.map.remove(&name.to_ascii_lowercase());
+    }
+    pub fn has(&self, name: &str) -> bool {
+        self.map.contains_key(&name.to_ascii_lowercase())
+    }
+    /// all values of a header
     pub fn get(&self, name: &str) -> Option<&Vec<String>> {
-        self.map.get(name)
+        self.map.get(&name.to_ascii_lowercase())
+    }
+    /// the values of a header combined like Headers.get() in js
+    pub fn get_combined(&self, name: &str) -> Option<String> {
+        self.get(name).map(|values| values.join(", "))
+    }
+    /// the name/value pairs sorted by name with combined values, set-cookie is not combined
+    pub fn entries(&self) -> Vec<(String, String)> {
+        let mut names: Vec<&String> = self.map.keys().collect();
+        names.sort();
+        let mut entries = vec![];
+        for name in names {
+            let values = &self.map[name];
+            if name.eq("set-cookie") {
+                for value in values {
+                    entries.push((name.clone(), value.clone()));
+                }
+            } else {
+                entries.push((name.clone(), values.join(", ")));
+            }
+        }
+        entries
     }
 }
 impl Default for Headers {
@@ -323,6 +389,7 @@ impl Default for Headers {
     }
 }
 
+#[derive(Clone)]
 pub struct Body {
     pub text: Option<String>,
     pub bytes: Option<Vec<u8>>,
@@ -332,7 +399,10 @@ impl Body {
 }
 
 pub struct Response {
-    pub body: Body,
+    /// the raw bytes of the body
+    pub body: Vec<u8>,
+    /// set when the body was consumed by text(), json(), arrayBuffer() and such
+    pub body_used: AtomicBool,
     pub headers: Headers,
     pub ok: bool,
     pub redirected: bool,
@@ -351,27 +421,242 @@ impl Response {
         });
         Ok(inst_res.1)
     }
-    pub async fn text(&self) -> Result<String, JsError> {
-        if let Some(text) = self.body.text.as_ref() {
-            Ok(text.clone())
-        } else if let Some(bytes) = self.body.bytes.as_ref() {
-            Ok(String::from_utf8(bytes.clone())
-                .map_err(|_e| JsError::new_str("could not convert to string (utf8 error)"))?)
+    /// mark the body as used, fails if it was used before
+    pub fn use_body(&self) -> Result<(), JsError> {
+        if self.body_used.swap(true, Ordering::SeqCst) {
+            Err(JsError::new_str("body stream already read"))
         } else {
-            Err(JsError::new_str("body had no content"))
+            Ok(())
         }
     }
+    pub fn content_type(&self) -> Option<&str> {
+        self.headers
+            .get("content-type")
+            .and_then(|values| values.first())
+            .map(|value| value.as_str())
+    }
+    /// decode the body with the charset of the content-type, utf-8 if there is no (known) charset
+    pub async fn text(&self) -> Result<String, JsError> {
+        let encoding = self
+            .content_type()
+            .and_then(|ct| content_type_param(ct, "charset"))
+            .and_then(|charset| encoding_rs::Encoding::for_label(charset.as_bytes()))
+            .unwrap_or(encoding_rs::UTF_8);
+        let (text, _encoding, _had_errors) = encoding.decode(self.body.as_slice());
+        Ok(text.into_owned())
+    }
     // todo impl some sort of take so we don;t copy bytes every time they are used (ReadableStream and such)
     pub async fn bytes(&self) -> Result<Vec<u8>, JsError> {
-        if let Some(bytes) = self.body.bytes.as_ref() {
-            Ok(bytes.clone())
+        Ok(self.body.clone())
+    }
+    pub async fn blob(&self) -> Result<Blob, JsError> {
+        Ok(Blob {
+            bytes: self.body.clone(),
+            content_type: self
+                .content_type()
+                .map(|ct| ct.to_ascii_lowercase())
+                .unwrap_or_default(),
+            name: None,
+        })
+    }
+    /// parse an application/x-www-form-urlencoded or multipart/form-data body
+    pub async fn form_data(&self) -> Result<FormData, JsError> {
+        let content_type = self.content_type().unwrap_or("");
+        match mime_essence(content_type).as_str() {
+            "application/x-www-form-urlencoded" => {
+                let mut form_data = FormData::new();
+                for (name, value) in url::form_urlencoded::parse(self.body.as_slice()) {
+                    form_data.append(name.as_ref(), FormDataValue::Text(value.into_owned()));
+                }
+                Ok(form_data)
+            }
+            "multipart/form-data" => {
+                let boundary = content_type_param(content_type, "boundary").ok_or_else(|| {
+                    JsError::new_str("multipart/form-data content-type has no boundary")
+                })?;
+                parse_multipart(self.body.as_slice(), boundary.as_str())
+            }
+            _ => Err(JsError::new_string(format!(
+                "could not parse body as FormData, unsupported content-type: {content_type}"
+            ))),
+        }
+    }
+}
+
+/// the data of a Blob or File
+#[derive(Clone)]
+pub struct Blob {
+    pub bytes: Vec<u8>,
+    /// the lowercase mime type or an empty string
+    pub content_type: String,
+    /// the file name, only set for a File
+    pub name: Option<String>,
+}
+
+#[derive(Clone)]
+pub enum FormDataValue {
+    Text(String),
+    File(Blob),
+}
+
+/// the entries of a FormData in order of insertion
+#[derive(Clone, Default)]
+pub struct FormData {
+    entries: Vec<(String, FormDataValue)>,
+}
+impl FormData {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn append(&mut self, name: &str, value: FormDataValue) {
+        self.entries.push((name.to_string(), value));
+    }
+    /// replace the first entry with name and remove the others, or append if there was none
+    pub fn set(&mut self, name: &str, value: FormDataValue) {
+        let mut value = Some(value);
+        self.entries.retain_mut(|(n, v)| {
+            if !n.eq(name) {
+                return true;
+            }
+            match value.take() {
+                Some(val) => {
+                    *v = val;
+                    true
+                }
+                None => false,
+            }
+        });
+        if let Some(value) = value {
+            self.append(name, value);
+        }
+    }
+    pub fn delete(&mut self, name: &str) {
+        self.entries.retain(|(n, _)| !n.eq(name));
+    }
+    pub fn has(&self, name: &str) -> bool {
+        self.entries.iter().any(|(n, _)| n.eq(name))
+    }
+    pub fn get(&self, name: &str) -> Option<&FormDataValue> {
+        self.entries
+            .iter()
+            .find(|(n, _)| n.eq(name))
+            .map(|(_, v)| v)
+    }
+    pub fn get_all(&self, name: &str) -> Vec<&FormDataValue> {
+        self.entries
+            .iter()
+            .filter(|(n, _)| n.eq(name))
+            .map(|(_, v)| v)
+            .collect()
+    }
+    pub fn entries(&self) -> &[(String, FormDataValue)] {
+        self.entries.as_slice()
+    }
+}
+
+/// the part of a content-type before the parameters, in lowercase
+fn mime_essence(content_type: &str) -> String {
+    content_type
+        .split(';')
+        .next()
+        .unwrap_or("")
+        .trim()
+        .to_ascii_lowercase()
+}
+
+/// split a header value like `form-data; name="a;b"` on the semicolons which are not quoted
+fn split_params(value: &str) -> Vec<&str> {
+    let mut parts = vec![];
+    let mut in_quotes = false;
+    let mut start = 0;
+    for (idx, c) in value.char_indices() {
+        match c {
+            '"' => in_quotes = !in_quotes,
+            ';' if !in_quotes => {
+                parts.push(value[start..idx].trim());
+                start = idx + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(value[start..].trim());
+    parts
+}
+
+/// a parameter of a header value like the charset of a content-type, without quotes
+fn content_type_param(value: &str, param: &str) -> Option<String> {
+    split_params(value).into_iter().skip(1).find_map(|part| {
+        let (name, val) = part.split_once('=')?;
+        if name.trim().eq_ignore_ascii_case(param) {
+            Some(val.trim().trim_matches('"').to_string())
         } else {
-            Err(JsError::new_str("body had no content"))
+            None
         }
+    })
+}
+
+fn find_bytes(haystack: &[u8], needle: &[u8], from: usize) -> Option<usize> {
+    if from > haystack.len() {
+        return None;
     }
-    pub async fn form_data(&self) -> Result<String, JsError> {
-        todo!()
+    haystack[from..]
+        .windows(needle.len())
+        .position(|window| window == needle)
+        .map(|pos| pos + from)
+}
+
+fn parse_multipart(body: &[u8], boundary: &str) -> Result<FormData, JsError> {
+    let invalid = || JsError::new_str("could not parse body as FormData, invalid multipart body");
+    let delimiter = format!("--{boundary}");
+    let next_delimiter = format!("\r\n--{boundary}");
+    let mut form_data = FormData::new();
+
+    let mut pos = find_bytes(body, delimiter.as_bytes(), 0).ok_or_else(invalid)? + delimiter.len();
+    loop {
+        // the last delimiter is followed by --
+        if body[pos..].starts_with(b"--") {
+            break;
+        }
+        if !body[pos..].starts_with(b"\r\n") {
+            return Err(invalid());
+        }
+        let headers_start = pos + 2;
+        let headers_end = find_bytes(body, b"\r\n\r\n", headers_start).ok_or_else(invalid)?;
+        let content_start = headers_end + 4;
+        let content_end =
+            find_bytes(body, next_delimiter.as_bytes(), content_start).ok_or_else(invalid)?;
+
+        let mut name = None;
+        let mut file_name = None;
+        let mut content_type = String::new();
+        let headers = String::from_utf8_lossy(&body[headers_start..headers_end]);
+        for line in headers.split("\r\n") {
+            let (header_name, header_value) = line.split_once(':').ok_or_else(invalid)?;
+            if header_name
+                .trim()
+                .eq_ignore_ascii_case("content-disposition")
+            {
+                name = content_type_param(header_value, "name");
+                file_name = content_type_param(header_value, "filename");
+            } else if header_name.trim().eq_ignore_ascii_case("content-type") {
+                content_type = header_value.trim().to_ascii_lowercase();
+            }
+        }
+        let name = name.ok_or_else(invalid)?;
+        let content = &body[content_start..content_end];
+        let value = match file_name {
+            Some(file_name) => FormDataValue::File(Blob {
+                bytes: content.to_vec(),
+                content_type,
+                name: Some(file_name),
+            }),
+            None => FormDataValue::Text(String::from_utf8_lossy(content).into_owned()),
+        };
+        form_data.append(name.as_str(), value);
+
+        pos = content_end + next_delimiter.len();
     }
+    Ok(form_data)
 }
 
 pub trait Request {
@@ -417,61 +702,28 @@ pub async fn do_fetch2(
             .await
             .map_err(|e| JsError::new_string(format!("reqwest error {e:?}")))?;
 
+        // a header may be present more than once (e.g. set-cookie), keep all values
         let mut headers = Headers::new();
         for hv in reqwest_resp.headers() {
-            headers.map.insert(
-                hv.0.to_string(),
-                vec![hv
-                    .1
-                    .to_str()
-                    .map_err(|e| JsError::new_string(format!("{e:?}")))?
-                    .to_string()],
-            );
+            headers.append(
+                hv.0.as_str(),
+                hv.1.to_str()
+                    .map_err(|e| JsError::new_string(format!("{e:?}")))?,
+            )?;
         }
 
         let ok = reqwest_resp.status().is_success();
         let status = reqwest_resp.status().as_u16();
 
-        let mut is_text = false;
-        if let Some(ct) = reqwest_resp.headers().get("content-type") {
-            let ct_str = ct
-                .to_str()
-                .map_err(|e| JsError::new_string(format!("{e:?}")))?;
-            if ct_str.eq("text/plain")
-                || ct_str.eq("text/html")
-                || ct_str.eq("application/json")
-                || ct_str.eq("image/svg+xml")
-            {
-                is_text = true;
-            }
-        }
-
-        let body = if is_text {
-            Body {
-                // todo support bytes, it would make more sense to make reqwest_resp a member of reponse, then we can also impl Response.arrayBuffer() or Response.blob()
-                text: Some(
-                    reqwest_resp
-                        .text()
-                        .await
-                        .map_err(|e| JsError::new_string(format!("{e:?}")))?,
-                ),
-                bytes: None,
-            }
-        } else {
-            let bytes: Vec<u8> = reqwest_resp
-                .bytes()
-                .await
-                .map_err(|e| JsError::new_string(format!("{e:?}")))?
-                .to_vec();
-
-            Body {
-                text: None,
-                bytes: Some(bytes),
-            }
-        };
+        let body: Vec<u8> = reqwest_resp
+            .bytes()
+            .await
+            .map_err(|e| JsError::new_string(format!("{e:?}")))?
+            .to_vec();
 
         let response: Response = Response {
             body,
+            body_used: AtomicBool::new(false),
             headers,
             ok,
             redirected: false,
@@ -488,6 +740,71 @@ pub async fn do_fetch2(
 
 #[cfg(test)]
 pub mod tests {
+    use crate::features::js_fetch::spec::{FormDataValue, Headers, Response};
+    use futures::executor::block_on;
+    use std::sync::atomic::AtomicBool;
+
+    fn response(content_type: &str, body: &[u8]) -> Response {
+        let mut headers = Headers::new();
+        headers
+            .append("Content-Type", content_type)
+            .expect("invalid header");
+        Response {
+            body: body.to_vec(),
+            body_used: AtomicBool::new(false),
+            headers,
+            ok: true,
+            redirected: false,
+            status: 200,
+            status_text: "",
+            response_type: "",
+            url: "".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_text_charset() {
+        let res = response("text/csv; charset=ISO-8859-1", b"caf\xe9;1");
+        assert_eq!(block_on(res.text()).expect("text failed"), "café;1");
+        let res = response("application/xml", "<a>é</a>".as_bytes());
+        assert_eq!(block_on(res.text()).expect("text failed"), "<a>é</a>");
+    }
+
+    #[test]
+    fn test_form_data() {
+        let res = response("application/x-www-form-urlencoded", b"a=1&b=x+y&a=%C3%A9");
+        let form_data = block_on(res.form_data()).expect("form_data failed");
+        let values: Vec<String> = form_data
+            .get_all("a")
+            .into_iter()
+            .map(|v| match v {
+                FormDataValue::Text(text) => text.clone(),
+                FormDataValue::File(_) => panic!("not a text value"),
+            })
+            .collect();
+        assert_eq!(values, vec!["1", "é"]);
+
+        let body = "--XyZ\r\nContent-Disposition: form-data; name=\"title\"\r\n\r\nhello\r\n--XyZ\r\nContent-Disposition: form-data; name=\"doc\"; filename=\"a;b.txt\"\r\nContent-Type: text/plain\r\n\r\nline1\r\nline2\r\n--XyZ--\r\n";
+        let res = response("multipart/form-data; boundary=\"XyZ\"", body.as_bytes());
+        let form_data = block_on(res.form_data()).expect("form_data failed");
+        assert_eq!(form_data.entries().len(), 2);
+        match form_data.get("title") {
+            Some(FormDataValue::Text(text)) => assert_eq!(text, "hello"),
+            _ => panic!("title was not a text value"),
+        }
+        match form_data.get("doc") {
+            Some(FormDataValue::File(blob)) => {
+                assert_eq!(blob.name.as_deref(), Some("a;b.txt"));
+                assert_eq!(blob.content_type, "text/plain");
+                assert_eq!(blob.bytes.as_slice(), b"line1\r\nline2");
+            }
+            _ => panic!("doc was not a file value"),
+        }
+
+        let res = response("text/plain", b"a=1");
+        assert!(block_on(res.form_data()).is_err());
+    }
+
     /*
     use futures::executor::block_on;
     use quickjs_runtime::builder::QuickJsRuntimeBuilder;
@@ -18,6 +18,10 @@ pub mod moduleloaders;
 pub mod modules;
 pub mod preprocessors;
 
+/// add the greco modules, features and preprocessors to a builder
+///
+/// the modules are added with their default (unrestricted) loaders, loaders which were added to
+/// the builder before calling this take precedence, e.g. a sandboxed FsModuleLoader
 pub fn init_greco_rt(builder: QuickJsRuntimeBuilder) -> QuickJsRuntimeBuilder {
     init_greco_rt2(builder, true, true, true)
 }
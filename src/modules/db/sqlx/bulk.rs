@@ -0,0 +1,448 @@
+//! bulk inserts and COPY
+//!
+//! insertMany inserts rows with multi-row INSERT statements, as many rows per statement as the
+//! parameter limit of the database allows, all statements are executed in one transaction
+//!
+//! copyIn and copyOut use COPY ... FROM STDIN and COPY ... TO STDOUT and are only supported on
+//! postgres
+//!
+//! # Example
+//!
+//! ```javascript
+//! async function test() {
+//!     let sqlxMod = await import('greco://sqlx');
+//!     let con = await sqlxMod.connectPostgres('db.local', 5432, 'user', 'pass', 'mydb');
+//!
+//!     let res = await con.insertMany('import.users', ['id', 'name'], [[1, 'Anna'], [2, 'Bob']]);
+//!     console.log("inserted %s rows", res.rowsAffected);
+//!
+//!     let sink = await con.copyIn("COPY users (id, name) FROM STDIN WITH (FORMAT csv)");
+//!     await sink.write('3,Carl\n');
+//!     await sink.write('4,Dora\n');
+//!     let rowCount = await sink.finish();
+//!
+//!     for await (let line of con.copyOut("COPY users TO STDOUT WITH (FORMAT csv)")) {
+//!         console.log(line);
+//!     }
+//! }
+//! ```
+//!
+//! # Methods
+//!
+//! ##insertMany(table: string, columns: Array<string>, rows: Array<Array<any>>): Promise<{rowsAffected: number}>
+//! the table and column names are quoted, a table name may be prefixed with a schema name
+//! ##copyIn(sql: string): Promise<CopyIn>
+//! ##copyOut(sql: string): QueryStream
+//! a QueryStream of strings, the data postgres sends, one row (including the line end) per value for
+//! the text and csv formats
+//!
+//! # CopyIn
+//!
+//! ##write(data: string | Uint8Array): Promise<void>
+//! ##finish(): Promise<number>
+//! end the COPY, resolves to the number of rows copied
+//! ##abort(message?: string): Promise<void>
+//!
+
+use crate::modules::db::sqlx::stream::{instantiate_stream, stream_channel, RowResult};
+use crate::modules::db::sqlx::{
+    bind_mysql_args, bind_pg_args, bind_sqlite_args, query_arg, Protocol, QueryArg, SqlxConnection,
+};
+use futures::TryStreamExt;
+use hirofa_utils::auto_id_map::AutoIdMap;
+use quickjs_runtime::jsutils::helper_tasks::add_helper_task_async;
+use quickjs_runtime::jsutils::jsproxies::JsProxy;
+use quickjs_runtime::jsutils::JsError;
+use quickjs_runtime::quickjsrealmadapter::QuickJsRealmAdapter;
+use quickjs_runtime::quickjsvalueadapter::QuickJsValueAdapter;
+use quickjs_runtime::values::JsValueFacade;
+use sqlx_lib::pool::PoolConnection;
+use sqlx_lib::postgres::{PgCopyIn, PgPoolCopyExt};
+use sqlx_lib::{Pool, Postgres};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::mpsc::Sender;
+
+type CopyInSink = tokio::sync::Mutex<Option<PgCopyIn<PoolConnection<Postgres>>>>;
+
+thread_local! {
+    static COPY_INS: RefCell<AutoIdMap<Arc<CopyInSink>>> = RefCell::new(AutoIdMap::new());
+}
+
+/// the maximum number of bind parameters in one statement
+fn max_params(protocol: &Protocol) -> usize {
+    match protocol {
+        Protocol::MySql | Protocol::Postgres => 65535,
+        Protocol::Sqlite => 32766,
+    }
+}
+
+/// quote a (schema qualified) table or column name
+fn quote_identifier(protocol: &Protocol, name: &str) -> String {
+    let quote = match protocol {
+        Protocol::MySql => "`",
+        Protocol::Postgres | Protocol::Sqlite => "\"",
+    };
+    name.split('.')
+        .map(|part| {
+            format!(
+                "{quote}{}{quote}",
+                part.replace(quote, format!("{quote}{quote}").as_str())
+            )
+        })
+        .collect::<Vec<String>>()
+        .join(".")
+}
+
+fn insert_sql(protocol: &Protocol, table: &str, columns: &[String], row_count: usize) -> String {
+    let columns_sql = columns
+        .iter()
+        .map(|column| quote_identifier(protocol, column))
+        .collect::<Vec<String>>()
+        .join(", ");
+    let mut sql = format!(
+        "INSERT INTO {} ({columns_sql}) VALUES ",
+        quote_identifier(protocol, table)
+    );
+    let mut param_idx = 0;
+    for row_idx in 0..row_count {
+        if row_idx > 0 {
+            sql.push_str(", ");
+        }
+        sql.push('(');
+        for col_idx in 0..columns.len() {
+            if col_idx > 0 {
+                sql.push_str(", ");
+            }
+            param_idx += 1;
+            match protocol {
+                Protocol::Postgres => sql.push_str(format!("${param_idx}").as_str()),
+                Protocol::MySql | Protocol::Sqlite => sql.push('?'),
+            }
+        }
+        sql.push(')');
+    }
+    sql
+}
+
+fn protocol(connection: &SqlxConnection) -> Protocol {
+    match connection {
+        SqlxConnection::PostgresConnection { .. } => Protocol::Postgres,
+        SqlxConnection::MySqlConnection { .. } => Protocol::MySql,
+        SqlxConnection::SqliteConnection { .. } => Protocol::Sqlite,
+        SqlxConnection::MockConnection { mock, .. } => mock.protocol(),
+    }
+}
+
+fn postgres_pool(connection: &SqlxConnection, method: &str) -> Result<Pool<Postgres>, JsError> {
+    match connection {
+        SqlxConnection::PostgresConnection { pool, .. } => match pool {
+            None => Err(JsError::new_str("pool was closed")),
+            Some(pool) => Ok(pool.clone()),
+        },
+        _ => Err(JsError::new_string(format!(
+            "{method} is only supported for postgres connections"
+        ))),
+    }
+}
+
+/// the insertMany() method of Connection
+pub(crate) fn insert_many(
+    realm: &QuickJsRealmAdapter,
+    connection: Arc<SqlxConnection>,
+    args: &[QuickJsValueAdapter],
+) -> Result<QuickJsValueAdapter, JsError> {
+    if args.len() != 3 || !args[0].is_string() || !args[1].is_array() || !args[2].is_array() {
+        return Err(JsError::new_str("insertMany requires three arguments: (table: string, columns: Array<string>, rows: Array<Array<any>>)"));
+    }
+    let protocol = protocol(&connection);
+    let table = args[0].to_string()?;
+
+    let mut columns = vec![];
+    for x in 0..realm.get_array_length(&args[1])? {
+        let column = realm.get_array_element(&args[1], x)?;
+        if !column.is_string() {
+            return Err(JsError::new_str("columns should be an Array of strings"));
+        }
+        columns.push(column.to_string()?);
+    }
+    if columns.is_empty() {
+        return Err(JsError::new_str("columns should not be empty"));
+    }
+
+    let mut rows: Vec<Vec<QueryArg>> = vec![];
+    for x in 0..realm.get_array_length(&args[2])? {
+        let row = realm.get_array_element(&args[2], x)?;
+        if !row.is_array() || realm.get_array_length(&row)? as usize != columns.len() {
+            return Err(JsError::new_string(format!(
+                "row {x} should be an Array of {} values",
+                columns.len()
+            )));
+        }
+        let mut values = vec![];
+        for y in 0..realm.get_array_length(&row)? {
+            values.push(query_arg(realm, &realm.get_array_element(&row, y)?)?);
+        }
+        rows.push(values);
+    }
+
+    // split the rows in batches which stay within the parameter limit
+    let rows_per_batch = (max_params(&protocol) / columns.len()).max(1);
+    let mut batches: Vec<(String, Vec<QueryArg>)> = vec![];
+    let mut rows_iter = rows.into_iter().peekable();
+    while rows_iter.peek().is_some() {
+        let batch: Vec<Vec<QueryArg>> = rows_iter.by_ref().take(rows_per_batch).collect();
+        let sql = insert_sql(&protocol, table.as_str(), &columns, batch.len());
+        batches.push((sql, batch.into_iter().flatten().collect()));
+    }
+
+    realm.create_resolving_promise_async(
+        async move {
+            let mut rows_affected: u64 = 0;
+            match &*connection {
+                SqlxConnection::PostgresConnection {
+                    pool: Some(pool), ..
+                } => {
+                    let mut tx = pool
+                        .begin()
+                        .await
+                        .map_err(|e| JsError::new_string(format!("{e:?}")))?;
+                    for (sql, batch_args) in batches {
+                        let qry_obj =
+                            bind_pg_args(sqlx_lib::query(sql.as_str()), batch_args).await?;
+                        rows_affected += qry_obj
+                            .execute(&mut *tx)
+                            .await
+                            .map_err(|e| JsError::new_string(format!("{e:?}")))?
+                            .rows_affected();
+                    }
+                    tx.commit()
+                        .await
+                        .map_err(|e| JsError::new_string(format!("{e:?}")))?;
+                }
+                SqlxConnection::MySqlConnection {
+                    pool: Some(pool), ..
+                } => {
+                    let mut tx = pool
+                        .begin()
+                        .await
+                        .map_err(|e| JsError::new_string(format!("{e:?}")))?;
+                    for (sql, batch_args) in batches {
+                        let qry_obj =
+                            bind_mysql_args(sqlx_lib::query(sql.as_str()), batch_args).await?;
+                        rows_affected += qry_obj
+                            .execute(&mut *tx)
+                            .await
+                            .map_err(|e| JsError::new_string(format!("{e:?}")))?
+                            .rows_affected();
+                    }
+                    tx.commit()
+                        .await
+                        .map_err(|e| JsError::new_string(format!("{e:?}")))?;
+                }
+                SqlxConnection::SqliteConnection {
+                    pool: Some(pool), ..
+                } => {
+                    let mut tx = pool
+                        .begin()
+                        .await
+                        .map_err(|e| JsError::new_string(format!("{e:?}")))?;
+                    for (sql, batch_args) in batches {
+                        let qry_obj =
+                            bind_sqlite_args(sqlx_lib::query(sql.as_str()), batch_args).await?;
+                        rows_affected += qry_obj
+                            .execute(&mut *tx)
+                            .await
+                            .map_err(|e| JsError::new_string(format!("{e:?}")))?
+                            .rows_affected();
+                    }
+                    tx.commit()
+                        .await
+                        .map_err(|e| JsError::new_string(format!("{e:?}")))?;
+                }
+                SqlxConnection::MockConnection { mock, .. } => {
+                    mock.record("BEGIN", vec![]);
+                    for (sql, batch_args) in batches {
+                        match mock.execute_batch(sql.as_str(), batch_args).await {
+                            Ok(batch_rows_affected) => rows_affected += batch_rows_affected,
+                            Err(e) => {
+                                mock.record("ROLLBACK", vec![]);
+                                return Err(e);
+                            }
+                        }
+                    }
+                    mock.record("COMMIT", vec![]);
+                }
+                _ => {
+                    return Err(JsError::new_str("not connected"));
+                }
+            }
+            let mut res = HashMap::new();
+            res.insert(
+                "rowsAffected".to_string(),
+                JsValueFacade::new_f64(rows_affected as f64),
+            );
+            Ok(JsValueFacade::Object { val: res })
+        },
+        |realm, res| realm.from_js_value_facade(res),
+    )
+}
+
+/// the copyIn() method of Connection, resolves to a CopyIn instance
+pub(crate) fn copy_in(
+    realm: &QuickJsRealmAdapter,
+    connection: Arc<SqlxConnection>,
+    args: &[QuickJsValueAdapter],
+) -> Result<QuickJsValueAdapter, JsError> {
+    if args.len() != 1 || !args[0].is_string() {
+        return Err(JsError::new_str(
+            "copyIn requires one argument: (sql: string)",
+        ));
+    }
+    let sql = args[0].to_string()?;
+    let pool = postgres_pool(&connection, "copyIn")?;
+    realm.create_resolving_promise_async(
+        async move {
+            pool.copy_in_raw(sql.as_str())
+                .await
+                .map_err(|e| JsError::new_string(format!("{e:?}")))
+        },
+        |realm, copy_in| {
+            let instance_id = COPY_INS.with(|rc| {
+                let map = &mut *rc.borrow_mut();
+                map.insert(Arc::new(tokio::sync::Mutex::new(Some(copy_in))))
+            });
+            realm.instantiate_proxy_with_id(&["greco", "db", "sqlx"], "CopyIn", instance_id)
+        },
+    )
+}
+
+async fn run_copy_out(pool: Pool<Postgres>, sql: String, sender: Sender<RowResult>) {
+    let res: Result<(), JsError> = async {
+        let mut stream = pool
+            .copy_out_raw(sql.as_str())
+            .await
+            .map_err(|e| JsError::new_string(format!("{e:?}")))?;
+        while let Some(chunk) = stream
+            .try_next()
+            .await
+            .map_err(|e| JsError::new_string(format!("{e:?}")))?
+        {
+            let line = JsValueFacade::new_string(String::from_utf8_lossy(&chunk).to_string());
+            if sender.send(Ok(line)).await.is_err() {
+                // the stream was returned or garbage collected
+                break;
+            }
+        }
+        Ok(())
+    }
+    .await;
+    if let Err(e) = res {
+        let _ = sender.send(Err(e)).await;
+    }
+}
+
+/// the copyOut() method of Connection, returns a QueryStream of the data postgres sends
+pub(crate) fn copy_out(
+    realm: &QuickJsRealmAdapter,
+    connection: Arc<SqlxConnection>,
+    args: &[QuickJsValueAdapter],
+) -> Result<QuickJsValueAdapter, JsError> {
+    if args.len() != 1 || !args[0].is_string() {
+        return Err(JsError::new_str(
+            "copyOut requires one argument: (sql: string)",
+        ));
+    }
+    let sql = args[0].to_string()?;
+    let pool = postgres_pool(&connection, "copyOut")?;
+    let (sender, receiver) = stream_channel();
+    let _unused = add_helper_task_async(run_copy_out(pool, sql, sender));
+    instantiate_stream(realm, receiver)
+}
+
+fn with_copy_in(instance_id: &usize) -> Result<Arc<CopyInSink>, JsError> {
+    COPY_INS.with(|rc| {
+        let map = &*rc.borrow();
+        map.get(instance_id)
+            .cloned()
+            .ok_or_else(|| JsError::new_str("no such CopyIn"))
+    })
+}
+
+pub(crate) fn create_copy_in_proxy(_realm: &QuickJsRealmAdapter) -> JsProxy {
+    JsProxy::new()
+        .namespace(&["greco", "db", "sqlx"])
+        .name("CopyIn")
+        .method("write", |_rt, realm, instance_id, args| {
+            let data = match args.first().map(|arg| realm.to_js_value_facade(arg)) {
+                Some(Ok(JsValueFacade::String { val })) => val.as_bytes().to_vec(),
+                Some(Ok(JsValueFacade::TypedArray { buffer, .. })) => buffer,
+                _ => {
+                    return Err(JsError::new_str(
+                        "write requires one argument: (data: string | Uint8Array)",
+                    ));
+                }
+            };
+            let sink = with_copy_in(instance_id)?;
+            realm.create_resolving_promise_async(
+                async move {
+                    match &mut *sink.lock().await {
+                        Some(copy_in) => {
+                            copy_in
+                                .send(data)
+                                .await
+                                .map_err(|e| JsError::new_string(format!("{e:?}")))?;
+                            Ok(())
+                        }
+                        None => Err(JsError::new_str("CopyIn was finished")),
+                    }
+                },
+                |realm, _res| realm.create_undefined(),
+            )
+        })
+        .method("finish", |_rt, realm, instance_id, _args| {
+            let sink = with_copy_in(instance_id)?;
+            realm.create_resolving_promise_async(
+                async move {
+                    match sink.lock().await.take() {
+                        Some(copy_in) => copy_in
+                            .finish()
+                            .await
+                            .map_err(|e| JsError::new_string(format!("{e:?}"))),
+                        None => Err(JsError::new_str("CopyIn was finished")),
+                    }
+                },
+                |realm, rows| realm.create_f64(rows as f64),
+            )
+        })
+        .method("abort", |_rt, realm, instance_id, args| {
+            let message = match args.first() {
+                Some(message) if message.is_string() => message.to_string()?,
+                _ => "aborted".to_string(),
+            };
+            let sink = with_copy_in(instance_id)?;
+            realm.create_resolving_promise_async(
+                async move {
+                    match sink.lock().await.take() {
+                        Some(copy_in) => copy_in
+                            .abort(message)
+                            .await
+                            .map_err(|e| JsError::new_string(format!("{e:?}"))),
+                        None => Err(JsError::new_str("CopyIn was finished")),
+                    }
+                },
+                |realm, _res| realm.create_undefined(),
+            )
+        })
+        .finalizer(|_rt, _realm, instance_id| {
+            let arc = COPY_INS.with(|rc| {
+                let map = &mut *rc.borrow_mut();
+                map.remove_opt(&instance_id)
+            });
+            // an unfinished COPY is aborted when the connection is used again
+            let _unused = add_helper_task_async(async move {
+                drop(arc);
+            });
+        })
+}
@@ -0,0 +1,159 @@
+//! timeouts and cancellation of queries
+//!
+//! query, execute and stream accept {timeoutMs: number, signal: AbortSignal} in their options,
+//! when the timeout expires or the signal is aborted the promise is rejected with a TimeoutError or
+//! an AbortError and the running statement is cancelled (pg_cancel_backend on postgres, KILL QUERY
+//! on mysql), a stream stops fetching rows and releases its connection
+//!
+//! # Example
+//!
+//! ```javascript
+//! async function test() {
+//!     let sqlxMod = await import('greco://sqlx');
+//!     let con = await sqlxMod.connectPostgres('db.local', 5432, 'user', 'pass', 'mydb');
+//!     let controller = new AbortController();
+//!     try {
+//!         await con.query('SELECT * FROM huge_report', null, null, {timeoutMs: 5000, signal: controller.signal});
+//!     } catch (ex) {
+//!         if (ex.name === 'TimeoutError') {
+//!             console.log('report took too long');
+//!         } else if (ex.name === 'AbortError') {
+//!             console.log('report was aborted');
+//!         }
+//!     }
+//! }
+//! ```
+//!
+//! any object with an aborted property and an addEventListener method which dispatches an 'abort'
+//! event can be used as signal
+//!
+
+use quickjs_runtime::jsutils::JsError;
+use quickjs_runtime::quickjsrealmadapter::QuickJsRealmAdapter;
+use quickjs_runtime::quickjsvalueadapter::QuickJsValueAdapter;
+use sqlx_lib::{MySql, Pool, Postgres};
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Notify;
+
+#[derive(Default)]
+pub(crate) struct Cancellation {
+    timeout: Option<Duration>,
+    abort: Option<Arc<Notify>>,
+}
+
+impl Cancellation {
+    /// parse timeoutMs and signal from the options of query or execute
+    pub(crate) fn parse(
+        realm: &QuickJsRealmAdapter,
+        options: &QuickJsValueAdapter,
+    ) -> Result<Self, JsError> {
+        let mut res = Self::default();
+
+        let timeout = realm.get_object_property(options, "timeoutMs")?;
+        if timeout.is_i32() {
+            res.timeout = Some(Duration::from_millis(timeout.to_i32().max(0) as u64));
+        } else if timeout.is_f64() {
+            res.timeout = Some(Duration::from_millis(timeout.to_f64().max(0.0) as u64));
+        } else if !timeout.is_null_or_undefined() {
+            return Err(JsError::new_str("timeoutMs should be a number"));
+        }
+
+        let signal = realm.get_object_property(options, "signal")?;
+        if signal.is_object() && !signal.is_null() {
+            let notify = Arc::new(Notify::new());
+            let aborted = realm.get_object_property(&signal, "aborted")?;
+            if aborted.is_bool() && aborted.to_bool() {
+                // stores a permit so the query is aborted right away
+                notify.notify_one();
+            } else {
+                let notify = notify.clone();
+                let on_abort = realm.create_function(
+                    "onAbort",
+                    move |realm, _this, _args| {
+                        notify.notify_one();
+                        realm.create_undefined()
+                    },
+                    1,
+                )?;
+                let evt_type = realm.create_string("abort")?;
+                realm.invoke_function_member(
+                    &signal,
+                    "addEventListener",
+                    &[&evt_type, &on_abort],
+                )?;
+            }
+            res.abort = Some(notify);
+        } else if !signal.is_null_or_undefined() {
+            return Err(JsError::new_str("signal should be an AbortSignal"));
+        }
+
+        Ok(res)
+    }
+
+    pub(crate) fn is_set(&self) -> bool {
+        self.timeout.is_some() || self.abort.is_some()
+    }
+
+    /// run a query, the outer Err is the TimeoutError or AbortError if the query was cancelled
+    pub(crate) async fn run<F, R>(&self, fut: F) -> Result<Result<R, JsError>, JsError>
+    where
+        F: Future<Output = Result<R, JsError>>,
+    {
+        let abort = async {
+            match &self.abort {
+                Some(notify) => notify.notified().await,
+                None => futures::future::pending().await,
+            }
+        };
+        let timeout_ms = self.timeout.unwrap_or_default().as_millis();
+        let timeout = async {
+            match self.timeout {
+                Some(timeout) => tokio::time::sleep(timeout).await,
+                None => futures::future::pending().await,
+            }
+        };
+        tokio::select! {
+            biased;
+            _ = abort => Err(JsError::new(
+                "AbortError".to_string(),
+                "the query was aborted".to_string(),
+                "".to_string(),
+            )),
+            res = fut => Ok(res),
+            _ = timeout => Err(JsError::new(
+                "TimeoutError".to_string(),
+                format!("the query timed out after {timeout_ms}ms"),
+                "".to_string(),
+            )),
+        }
+    }
+}
+
+/// cancel the statement running on the postgres connection with the given pid
+pub(crate) async fn cancel_postgres(pool: &Pool<Postgres>, pid: i32) {
+    log::debug!("cancelling statement of postgres backend {}", pid);
+    if let Err(e) = sqlx_lib::query("SELECT pg_cancel_backend($1)")
+        .bind(pid)
+        .execute(pool)
+        .await
+    {
+        log::error!("could not cancel statement of backend {}: {:?}", pid, e);
+    }
+}
+
+/// cancel the statement running on the mysql connection with the given id
+pub(crate) async fn cancel_mysql(pool: &Pool<MySql>, connection_id: u64) {
+    log::debug!("cancelling statement of mysql connection {}", connection_id);
+    if let Err(e) = sqlx_lib::query(format!("KILL QUERY {connection_id}").as_str())
+        .execute(pool)
+        .await
+    {
+        log::error!(
+            "could not cancel statement of connection {}: {:?}",
+            connection_id,
+            e
+        );
+    }
+}
@@ -0,0 +1,308 @@
+//! LISTEN / NOTIFY for Postgres connections
+//!
+//! notifications are dispatched as 'notification' events on the Connection, they are received by
+//! a dedicated connection which is opened on the first call to listen()
+//!
+//! # Example
+//!
+//! ```javascript
+//! async function test() {
+//!     let sqlxMod = await import('greco://sqlx');
+//!     let con = await sqlxMod.connectPostgres('db.local', 5432, 'user', 'pass', 'mydb');
+//!     con.addEventListener('notification', (evt) => {
+//!         console.log("got %s on channel %s", evt.payload, evt.channel);
+//!     });
+//!     await con.listen('jobs');
+//!     await con.notify('jobs', 'job 12 is done');
+//!     // stop listening
+//!     // await con.unlisten('jobs');
+//! }
+//! ```
+//!
+//! the listener is stopped when the Connection is garbage collected so keep a reference to it
+//! for as long as you want to receive notifications
+//!
+//! # Events
+//!
+//! ##notification
+//! {channel: string, payload: string, processId: number}
+//!
+//! # Methods
+//!
+//! ##listen(channel: string): Promise<void>
+//! ##unlisten(channel: string): Promise<void>
+//! ##notify(channel: string, payload: string): Promise<void>
+//!
+
+use crate::modules::db::sqlx::SqlxConnection;
+use quickjs_runtime::jsutils::helper_tasks::add_helper_task_async;
+use quickjs_runtime::jsutils::JsError;
+use quickjs_runtime::quickjsrealmadapter::QuickJsRealmAdapter;
+use quickjs_runtime::quickjsvalueadapter::QuickJsValueAdapter;
+use sqlx_lib::postgres::{PgListener, PgNotification};
+use sqlx_lib::{Pool, Postgres};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use tokio::sync::oneshot;
+
+enum ListenCommand {
+    Listen(String, oneshot::Sender<Result<(), JsError>>),
+    Unlisten(String, oneshot::Sender<Result<(), JsError>>),
+}
+
+thread_local! {
+    // command senders of the listener tasks by Connection proxy instance id, dropping the sender
+    // stops the task
+    static LISTENERS: RefCell<HashMap<usize, UnboundedSender<ListenCommand>>> = RefCell::new(HashMap::new());
+}
+
+fn postgres_pool(connection: &SqlxConnection) -> Result<Pool<Postgres>, JsError> {
+    match connection {
+        SqlxConnection::PostgresConnection { pool, .. } => match pool {
+            None => Err(JsError::new_str("pool was closed")),
+            Some(pool) => Ok(pool.clone()),
+        },
+        _ => Err(JsError::new_str(
+            "listen and notify are only supported for postgres connections",
+        )),
+    }
+}
+
+fn channel_arg(args: &[QuickJsValueAdapter], method: &str) -> Result<String, JsError> {
+    match args.first() {
+        Some(channel) if channel.is_string() => channel.to_string(),
+        _ => Err(JsError::new_string(format!(
+            "{method} requires one argument: (channel: string)"
+        ))),
+    }
+}
+
+fn dispatch_notification(
+    realm: &QuickJsRealmAdapter,
+    instance_id: usize,
+    notification: PgNotification,
+) -> Result<(), JsError> {
+    let evt_obj = realm.create_object()?;
+    let channel_ref = realm.create_string(notification.channel())?;
+    realm.set_object_property(&evt_obj, "channel", &channel_ref)?;
+    let payload_ref = realm.create_string(notification.payload())?;
+    realm.set_object_property(&evt_obj, "payload", &payload_ref)?;
+    let process_id_ref = realm.create_f64(notification.process_id() as f64)?;
+    realm.set_object_property(&evt_obj, "processId", &process_id_ref)?;
+    realm.dispatch_proxy_event(
+        &["greco", "db", "sqlx"],
+        "Connection",
+        &instance_id,
+        "notification",
+        &evt_obj,
+    )?;
+    Ok(())
+}
+
+/// run a PgListener until the command sender is dropped, dispatcher returns false if the runtime
+/// is gone
+async fn run_listener<D>(
+    instance_id: usize,
+    pool: Pool<Postgres>,
+    mut commands: UnboundedReceiver<ListenCommand>,
+    dispatcher: D,
+) where
+    D: Fn(PgNotification) -> bool + Send + 'static,
+{
+    let mut listener = match PgListener::connect_with(&pool).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            // fail the pending command(s), the next listen() starts a new listener
+            commands.close();
+            while let Some(cmd) = commands.recv().await {
+                let err = JsError::new_string(format!("{e:?}"));
+                match cmd {
+                    ListenCommand::Listen(_, resolver) | ListenCommand::Unlisten(_, resolver) => {
+                        let _ = resolver.send(Err(err));
+                    }
+                }
+            }
+            return;
+        }
+    };
+    loop {
+        tokio::select! {
+            cmd = commands.recv() => match cmd {
+                None => break,
+                Some(ListenCommand::Listen(channel, resolver)) => {
+                    let res = listener
+                        .listen(channel.as_str())
+                        .await
+                        .map_err(|e| JsError::new_string(format!("{e:?}")));
+                    let _ = resolver.send(res);
+                }
+                Some(ListenCommand::Unlisten(channel, resolver)) => {
+                    let res = listener
+                        .unlisten(channel.as_str())
+                        .await
+                        .map_err(|e| JsError::new_string(format!("{e:?}")));
+                    let _ = resolver.send(res);
+                }
+            },
+            notification = listener.recv() => match notification {
+                Ok(notification) => {
+                    log::trace!("Connection {} got notification on {}", instance_id, notification.channel());
+                    if !dispatcher(notification) {
+                        break;
+                    }
+                }
+                Err(e) => {
+                    log::error!("listener for Connection {} failed: {:?}", instance_id, e);
+                    break;
+                }
+            }
+        }
+    }
+}
+
+fn send_command(
+    realm: &QuickJsRealmAdapter,
+    connection: Arc<SqlxConnection>,
+    instance_id: usize,
+    cmd: ListenCommand,
+) -> Result<(), JsError> {
+    let pool = postgres_pool(&connection)?;
+    LISTENERS.with(|rc| {
+        let listeners = &mut *rc.borrow_mut();
+        let closed = listeners
+            .get(&instance_id)
+            .map(|sender| sender.is_closed())
+            .unwrap_or(true);
+        if closed {
+            let (sender, receiver) = unbounded_channel();
+            let rti_ref = realm.get_runtime_facade_inner();
+            let realm_id = realm.get_realm_id().to_string();
+            let dispatcher = move |notification: PgNotification| {
+                // in the listener task here
+                if let Some(rt_ref) = rti_ref.upgrade() {
+                    let realm_id = realm_id.clone();
+                    rt_ref.add_rt_task_to_event_loop_void(move |runtime| {
+                        if let Some(realm) = runtime.get_realm(realm_id.as_str()) {
+                            if let Err(e) = dispatch_notification(realm, instance_id, notification)
+                            {
+                                log::error!("notification dispatch failed: {}", e);
+                            }
+                        } else {
+                            log::error!("realm not found");
+                        }
+                    });
+                    true
+                } else {
+                    false
+                }
+            };
+            let _unused =
+                add_helper_task_async(run_listener(instance_id, pool, receiver, dispatcher));
+            listeners.insert(instance_id, sender);
+        }
+        listeners
+            .get(&instance_id)
+            .expect("no such listener")
+            .send(cmd)
+            .map_err(|_| JsError::new_str("listener was closed"))
+    })
+}
+
+fn listen_command(
+    realm: &QuickJsRealmAdapter,
+    connection: Arc<SqlxConnection>,
+    instance_id: usize,
+    cmd: ListenCommand,
+    receiver: oneshot::Receiver<Result<(), JsError>>,
+) -> Result<QuickJsValueAdapter, JsError> {
+    send_command(realm, connection, instance_id, cmd)?;
+    realm.create_resolving_promise_async(
+        async move {
+            receiver
+                .await
+                .map_err(|_| JsError::new_str("listener was closed"))?
+        },
+        |realm, _res| realm.create_undefined(),
+    )
+}
+
+/// start listening on a channel, resolves when the LISTEN was executed
+pub(crate) fn listen(
+    realm: &QuickJsRealmAdapter,
+    connection: Arc<SqlxConnection>,
+    instance_id: usize,
+    args: &[QuickJsValueAdapter],
+) -> Result<QuickJsValueAdapter, JsError> {
+    let channel = channel_arg(args, "listen")?;
+    let (sender, receiver) = oneshot::channel();
+    listen_command(
+        realm,
+        connection,
+        instance_id,
+        ListenCommand::Listen(channel, sender),
+        receiver,
+    )
+}
+
+/// stop listening on a channel
+pub(crate) fn unlisten(
+    realm: &QuickJsRealmAdapter,
+    connection: Arc<SqlxConnection>,
+    instance_id: usize,
+    args: &[QuickJsValueAdapter],
+) -> Result<QuickJsValueAdapter, JsError> {
+    let channel = channel_arg(args, "unlisten")?;
+    postgres_pool(&connection)?;
+    let has_listener = LISTENERS.with(|rc| rc.borrow().contains_key(&instance_id));
+    if !has_listener {
+        // nothing to unlisten
+        return realm.create_resolving_promise_async(async move { Ok(()) }, |realm, _res| {
+            realm.create_undefined()
+        });
+    }
+    let (sender, receiver) = oneshot::channel();
+    listen_command(
+        realm,
+        connection,
+        instance_id,
+        ListenCommand::Unlisten(channel, sender),
+        receiver,
+    )
+}
+
+/// send a notification with pg_notify
+pub(crate) fn notify(
+    realm: &QuickJsRealmAdapter,
+    connection: Arc<SqlxConnection>,
+    args: &[QuickJsValueAdapter],
+) -> Result<QuickJsValueAdapter, JsError> {
+    if args.len() != 2 || !args[0].is_string() || !args[1].is_string() {
+        return Err(JsError::new_str(
+            "notify requires two arguments: (channel: string, payload: string)",
+        ));
+    }
+    let channel = args[0].to_string()?;
+    let payload = args[1].to_string()?;
+    let pool = postgres_pool(&connection)?;
+    realm.create_resolving_promise_async(
+        async move {
+            sqlx_lib::query("SELECT pg_notify($1, $2)")
+                .bind(channel)
+                .bind(payload)
+                .execute(&pool)
+                .await
+                .map_err(|e| JsError::new_string(format!("{e:?}")))?;
+            Ok(())
+        },
+        |realm, _res| realm.create_undefined(),
+    )
+}
+
+/// stop the listener of a Connection, called when the Connection is finalized
+pub(crate) fn drop_listener(instance_id: &usize) {
+    LISTENERS.with(|rc| {
+        let _ = rc.borrow_mut().remove(instance_id);
+    });
+}
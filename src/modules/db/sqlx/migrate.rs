@@ -0,0 +1,309 @@
+//! greco://sqlx/migrate, schema migrations from a dir of numbered .sql files
+//!
+//! every file in the dir which ends with .sql is a migration, its name starts with the version
+//! number (e.g. 001_create_users.sql), migrations are applied in order of their version and each
+//! migration is applied in its own Transaction (note that mysql commits DDL implicitly)
+//!
+//! the applied versions are recorded in a migrations table (greco_migrations by default) together
+//! with a sha-256 checksum of the file, when the file of an applied migration changed migrate
+//! rejects before applying anything
+//!
+//! the dir is a path like the paths of greco://fs, relative paths are resolved from the working
+//! dir of the process, when the loader was sandboxed with SqlxModuleLoader::fs_sandbox the dir and
+//! the migration files should be readable for scripts, otherwise migrate rejects with EACCES
+//!
+//! # Example
+//!
+//! ```javascript
+//! async function test() {
+//!     let sqlxMod = await import('greco://sqlx');
+//!     let migrateMod = await import('greco://sqlx/migrate');
+//!     let con = await sqlxMod.connectPostgres('db.local', 5432, 'user', 'pass', 'mydb');
+//!
+//!     let pending = await migrateMod.migrate(con, './migrations', {dryRun: true});
+//!     console.log("would apply %s", pending.map(m => m.name).join(', '));
+//!
+//!     let applied = await migrateMod.migrate(con, './migrations');
+//! }
+//! ```
+//!
+//! # Methods
+//!
+//! ##migrate(con: Connection, dir: string, options?: {dryRun?: boolean, table?: string}): Promise<Array<{version: number, name: string}>>
+//! resolves to the migrations which were applied, or with dryRun the migrations which would be
+//! applied, a dry run does not create the migrations table
+//!
+
+use crate::modules::db::sqlx::{with_connection, Protocol, SqlxConnection};
+use crate::modules::io::fs::permissions::{Access, FsPermissions};
+use quickjs_runtime::jsutils::JsError;
+use quickjs_runtime::quickjsrealmadapter::QuickJsRealmAdapter;
+use quickjs_runtime::quickjsvalueadapter::QuickJsValueAdapter;
+use quickjs_runtime::values::JsValueFacade;
+use sha2::{Digest, Sha256};
+use sqlx_lib::{Database, Encode, Executor, FromRow, IntoArguments, Pool, Type};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const DEFAULT_TABLE: &str = "greco_migrations";
+
+struct MigrationFile {
+    version: i64,
+    name: String,
+    sql: String,
+    checksum: String,
+}
+
+fn checksum(sql: &str) -> String {
+    Sha256::digest(sql.as_bytes())
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+/// read the .sql files of dir ordered by version, this does blocking io
+fn read_migrations(permissions: &FsPermissions, dir: &str) -> Result<Vec<MigrationFile>, JsError> {
+    let real_dir = permissions.check(dir, Access::Read, true, "opendir")?;
+    let entries = std::fs::read_dir(real_dir)
+        .map_err(|e| JsError::new_string(format!("could not read migrations dir {dir}: {e}")))?;
+    let mut files = vec![];
+    for entry in entries {
+        let entry = entry.map_err(|e| JsError::new_string(format!("{e:?}")))?;
+        let path = entry.path();
+        if !path.is_file() || path.extension().map(|ext| ext != "sql").unwrap_or(true) {
+            continue;
+        }
+        let name = entry.file_name().to_string_lossy().to_string();
+        let digits: String = name.chars().take_while(|c| c.is_ascii_digit()).collect();
+        let version = digits.parse::<i64>().map_err(|_| {
+            JsError::new_string(format!(
+                "migration {name} does not start with a version number"
+            ))
+        })?;
+        // a file may be a symlink to outside of the allowed roots
+        let real_path = permissions.check(&path.to_string_lossy(), Access::Read, true, "open")?;
+        let sql = std::fs::read_to_string(real_path)
+            .map_err(|e| JsError::new_string(format!("could not read migration {name}: {e}")))?;
+        files.push(MigrationFile {
+            version,
+            checksum: checksum(sql.as_str()),
+            name,
+            sql,
+        });
+    }
+    files.sort_by_key(|f| f.version);
+    for pair in files.windows(2) {
+        if pair[0].version == pair[1].version {
+            return Err(JsError::new_string(format!(
+                "migrations {} and {} have the same version",
+                pair[0].name, pair[1].name
+            )));
+        }
+    }
+    Ok(files)
+}
+
+/// apply the migrations which were not applied before, returns the (to be) applied migrations
+async fn run_migrations<DB>(
+    pool: &Pool<DB>,
+    protocol: Protocol,
+    table: &str,
+    files: Vec<MigrationFile>,
+    dry_run: bool,
+) -> Result<Vec<(i64, String)>, JsError>
+where
+    DB: Database,
+    for<'c> &'c mut DB::Connection: Executor<'c, Database = DB>,
+    for<'q> DB::Arguments<'q>: IntoArguments<'q, DB>,
+    (i64, String): for<'r> FromRow<'r, DB::Row>,
+    i64: for<'q> Encode<'q, DB> + Type<DB>,
+    String: for<'q> Encode<'q, DB> + Type<DB>,
+{
+    if !dry_run {
+        let create_sql = format!(
+            "CREATE TABLE IF NOT EXISTS {table} (version BIGINT PRIMARY KEY, name VARCHAR(255) NOT NULL, checksum VARCHAR(64) NOT NULL, applied_at BIGINT NOT NULL)"
+        );
+        sqlx_lib::query(create_sql.as_str())
+            .execute(pool)
+            .await
+            .map_err(|e| JsError::new_string(format!("{e:?}")))?;
+    }
+
+    let select_sql = format!("SELECT version, checksum FROM {table}");
+    let applied: HashMap<i64, String> =
+        match sqlx_lib::query_as::<DB, (i64, String)>(select_sql.as_str())
+            .fetch_all(pool)
+            .await
+        {
+            Ok(rows) => rows.into_iter().collect(),
+            // a dry run against a database which was never migrated
+            Err(_) if dry_run => HashMap::new(),
+            Err(e) => return Err(JsError::new_string(format!("{e:?}"))),
+        };
+
+    let mut pending = vec![];
+    for file in files {
+        match applied.get(&file.version) {
+            Some(applied_checksum) => {
+                if !applied_checksum.eq(&file.checksum) {
+                    return Err(JsError::new_string(format!(
+                        "migration {} was changed after it was applied (checksum mismatch)",
+                        file.name
+                    )));
+                }
+            }
+            None => pending.push(file),
+        }
+    }
+
+    let mut res = vec![];
+    if dry_run {
+        for file in pending {
+            res.push((file.version, file.name));
+        }
+        return Ok(res);
+    }
+
+    let insert_sql = match protocol {
+        Protocol::Postgres => format!(
+            "INSERT INTO {table} (version, name, checksum, applied_at) VALUES ($1, $2, $3, $4)"
+        ),
+        Protocol::MySql | Protocol::Sqlite => {
+            format!("INSERT INTO {table} (version, name, checksum, applied_at) VALUES (?, ?, ?, ?)")
+        }
+    };
+    for file in pending {
+        log::info!("applying migration {}", file.name);
+        let applied_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as i64)
+            .unwrap_or(0);
+        let mut tx = pool
+            .begin()
+            .await
+            .map_err(|e| JsError::new_string(format!("{e:?}")))?;
+        sqlx_lib::raw_sql(file.sql.as_str())
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| JsError::new_string(format!("migration {} failed: {e:?}", file.name)))?;
+        sqlx_lib::query(insert_sql.as_str())
+            .bind(file.version)
+            .bind(file.name.clone())
+            .bind(file.checksum)
+            .bind(applied_at)
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| JsError::new_string(format!("{e:?}")))?;
+        tx.commit()
+            .await
+            .map_err(|e| JsError::new_string(format!("{e:?}")))?;
+        res.push((file.version, file.name));
+    }
+    Ok(res)
+}
+
+fn migrate(
+    realm: &QuickJsRealmAdapter,
+    args: &[QuickJsValueAdapter],
+    fs_permissions: &Arc<FsPermissions>,
+) -> Result<QuickJsValueAdapter, JsError> {
+    if !(args.len() >= 2 && args[0].is_proxy_instance() && args[1].is_string()) {
+        return Err(JsError::new_str(
+            "migrate requires 2 or 3 args (con: Connection, dir: string, options?: {dryRun?: boolean, table?: string})",
+        ));
+    }
+    let p_data = realm.get_proxy_instance_info(&args[0])?;
+    if !p_data.0.eq("greco.db.sqlx.Connection") {
+        return Err(JsError::new_str(
+            "migrate expects a Connection as first arg",
+        ));
+    }
+    let connection = with_connection(p_data.1, |con| con.clone());
+    let dir = args[1].to_string()?;
+
+    let mut dry_run = false;
+    let mut table = DEFAULT_TABLE.to_string();
+    if let Some(options) = args.get(2).filter(|o| o.is_object() && !o.is_null()) {
+        let dry_run_opt = realm.get_object_property(options, "dryRun")?;
+        if dry_run_opt.is_bool() {
+            dry_run = dry_run_opt.to_bool();
+        } else if !dry_run_opt.is_null_or_undefined() {
+            return Err(JsError::new_str("dryRun should be a boolean"));
+        }
+        let table_opt = realm.get_object_property(options, "table")?;
+        if table_opt.is_string() {
+            table = table_opt.to_string()?;
+        } else if !table_opt.is_null_or_undefined() {
+            return Err(JsError::new_str("table should be a string"));
+        }
+    }
+    // the table name is put in the sql as is
+    if table.is_empty()
+        || !table
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+    {
+        return Err(JsError::new_string(format!(
+            "invalid migrations table name: {table}"
+        )));
+    }
+
+    let fs_permissions = fs_permissions.clone();
+    realm.create_resolving_promise_async(
+        async move {
+            let files =
+                tokio::task::spawn_blocking(move || read_migrations(&fs_permissions, dir.as_str()))
+                    .await
+                    .map_err(|e| JsError::new_string(format!("{e:?}")))??;
+            let applied = match &*connection {
+                SqlxConnection::PostgresConnection {
+                    pool: Some(pool), ..
+                } => {
+                    run_migrations(pool, Protocol::Postgres, table.as_str(), files, dry_run).await?
+                }
+                SqlxConnection::MySqlConnection {
+                    pool: Some(pool), ..
+                } => run_migrations(pool, Protocol::MySql, table.as_str(), files, dry_run).await?,
+                SqlxConnection::SqliteConnection {
+                    pool: Some(pool), ..
+                } => run_migrations(pool, Protocol::Sqlite, table.as_str(), files, dry_run).await?,
+                SqlxConnection::MockConnection { .. } => {
+                    return Err(JsError::new_str(
+                        "migrate is not supported on a mock Connection",
+                    ));
+                }
+                _ => {
+                    return Err(JsError::new_str("not connected"));
+                }
+            };
+            let val = applied
+                .into_iter()
+                .map(|(version, name)| {
+                    let mut obj = HashMap::new();
+                    obj.insert(
+                        "version".to_string(),
+                        JsValueFacade::new_f64(version as f64),
+                    );
+                    obj.insert("name".to_string(), JsValueFacade::new_string(name));
+                    JsValueFacade::Object { val: obj }
+                })
+                .collect();
+            Ok(JsValueFacade::Array { val })
+        },
+        |realm, res| realm.from_js_value_facade(res),
+    )
+}
+
+pub(crate) fn init_exports(
+    realm: &QuickJsRealmAdapter,
+    fs_permissions: &Arc<FsPermissions>,
+) -> Result<Vec<(&'static str, QuickJsValueAdapter)>, JsError> {
+    let fs_permissions = fs_permissions.clone();
+    let migrate_func = realm.create_function(
+        "migrate",
+        move |realm, _this, args| migrate(realm, args, &fs_permissions),
+        3,
+    )?;
+    Ok(vec![("migrate", migrate_func)])
+}
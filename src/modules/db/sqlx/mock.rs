@@ -0,0 +1,375 @@
+//! a mock database for testing scripts which use greco://sqlx without a database server
+//!
+//! a MockDatabase is registered from rust under a name, scripts get a Connection for it with
+//! getConnection(name), every query and execute is recorded (after the named parameters were
+//! rewritten for the protocol of the mock) and answered with the next scripted result
+//!
+//! transactions and savepoints record BEGIN, COMMIT, ROLLBACK, SAVEPOINT x, RELEASE SAVEPOINT x and
+//! ROLLBACK TO SAVEPOINT x, those do not take a scripted result
+//!
+//! when no scripted result is left a query resolves to no rows and an execute to
+//! {rowsAffected: 0, lastInsertId: 0, rows: []}
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use green_copper_runtime::modules::db::sqlx::{register_mock, MockDatabase, MockResult, MockValue, Protocol};
+//! use std::sync::Arc;
+//!
+//! let mock = Arc::new(MockDatabase::new(Protocol::Postgres));
+//! mock.push_result(MockResult::Rows {
+//!     columns: vec!["id".to_string(), "name".to_string()],
+//!     rows: vec![vec![MockValue::Number(1.0), MockValue::String("Anna".to_string())]],
+//! });
+//! register_mock("main", mock.clone());
+//!
+//! // run a script which does getConnection('main').query('SELECT id, name FROM users WHERE id = :id', {id: 1}, null)
+//!
+//! let queries = mock.queries();
+//! assert_eq!(queries[0].sql, "SELECT id, name FROM users WHERE id = $1");
+//! assert_eq!(queries[0].params, vec![MockValue::Number(1.0)]);
+//! ```
+//!
+//! listen, notify, copyIn, copyOut and migrate are not supported on a mock Connection
+//!
+
+use crate::modules::db::sqlx::observer::Observation;
+use crate::modules::db::sqlx::registry::{register_connection, REGISTERED_POOL_PREFIX};
+use crate::modules::db::sqlx::stream::RowResult;
+use crate::modules::db::sqlx::{
+    date_time_arg, invoke_js_function, Protocol, QueryArg, QueryOptions, RowMode, SqlxConnection,
+};
+use quickjs_runtime::jsutils::JsError;
+use quickjs_runtime::values::{JsValueFacade, TypedArrayType};
+use sqlx_lib::types::time::format_description::well_known::Rfc3339;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc::Sender;
+
+/// a parameter of a recorded query or a column value of a scripted row
+#[derive(Clone, Debug, PartialEq)]
+pub enum MockValue {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Bytes(Vec<u8>),
+    /// an object passed as parameter, as JSON
+    Json(String),
+    /// milliseconds since the epoch, a Date passed as parameter or a timestamp in a row which is
+    /// delivered to the script as an RFC 3339 string
+    Date(f64),
+    Array(Vec<MockValue>),
+}
+
+impl MockValue {
+    async fn from_query_arg(arg: QueryArg) -> Result<Self, JsError> {
+        Ok(match arg {
+            QueryArg::Date(millis) => MockValue::Date(millis),
+            QueryArg::Array(elements) => {
+                let mut values = vec![];
+                for element in elements {
+                    values.push(Box::pin(Self::from_query_arg(element)).await?);
+                }
+                MockValue::Array(values)
+            }
+            QueryArg::Value(value) => match value {
+                JsValueFacade::I32 { val } => MockValue::Number(val as f64),
+                JsValueFacade::F64 { val } => MockValue::Number(val),
+                JsValueFacade::String { val } => MockValue::String(val.to_string()),
+                JsValueFacade::Boolean { val } => MockValue::Bool(val),
+                JsValueFacade::JsObject { cached_object } => {
+                    MockValue::Json(cached_object.to_json_string().await?)
+                }
+                JsValueFacade::TypedArray { buffer, .. } => MockValue::Bytes(buffer),
+                _ => MockValue::Null,
+            },
+        })
+    }
+
+    fn to_js_value_facade(&self) -> Result<JsValueFacade, JsError> {
+        Ok(match self {
+            MockValue::Null => JsValueFacade::Null,
+            MockValue::Bool(val) => JsValueFacade::new_bool(*val),
+            MockValue::Number(val) => JsValueFacade::new_f64(*val),
+            MockValue::String(val) => JsValueFacade::new_string(val.clone()),
+            MockValue::Bytes(val) => JsValueFacade::TypedArray {
+                buffer: val.clone(),
+                array_type: TypedArrayType::Uint8,
+            },
+            MockValue::Json(val) => JsValueFacade::JsonStr { json: val.clone() },
+            MockValue::Date(millis) => JsValueFacade::new_string(
+                date_time_arg(*millis)?
+                    .format(&Rfc3339)
+                    .map_err(|e| JsError::new_string(format!("{e}")))?,
+            ),
+            MockValue::Array(values) => JsValueFacade::Array {
+                val: values
+                    .iter()
+                    .map(|v| v.to_js_value_facade())
+                    .collect::<Result<Vec<_>, _>>()?,
+            },
+        })
+    }
+}
+
+/// a query as it was sent to the mock
+#[derive(Clone, Debug, PartialEq)]
+pub struct RecordedQuery {
+    pub sql: String,
+    pub params: Vec<MockValue>,
+}
+
+/// a scripted result, results are used in the order in which they were pushed
+pub enum MockResult {
+    /// the rows of a query, for an execute these are the rows of a RETURNING clause and
+    /// rowsAffected is the number of rows
+    Rows {
+        columns: Vec<String>,
+        rows: Vec<Vec<MockValue>>,
+    },
+    Execute {
+        rows_affected: u64,
+        last_insert_id: u64,
+    },
+    /// the query or execute is rejected with this message
+    Error(String),
+}
+
+pub struct MockDatabase {
+    protocol: Protocol,
+    queries: Mutex<Vec<RecordedQuery>>,
+    results: Mutex<VecDeque<MockResult>>,
+}
+
+impl MockDatabase {
+    /// create a mock, named parameters are rewritten as they would be for protocol
+    pub fn new(protocol: Protocol) -> Self {
+        Self {
+            protocol,
+            queries: Mutex::new(vec![]),
+            results: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    pub fn protocol(&self) -> Protocol {
+        self.protocol
+    }
+
+    /// add a result for the next query or execute which has no result yet
+    pub fn push_result(&self, result: MockResult) {
+        self.results
+            .lock()
+            .expect("could not lock mutex")
+            .push_back(result);
+    }
+
+    /// the queries which were recorded so far, in order
+    pub fn queries(&self) -> Vec<RecordedQuery> {
+        self.queries.lock().expect("could not lock mutex").clone()
+    }
+
+    pub fn clear_queries(&self) {
+        self.queries.lock().expect("could not lock mutex").clear();
+    }
+
+    pub(crate) fn record(&self, sql: &str, params: Vec<MockValue>) {
+        self.queries
+            .lock()
+            .expect("could not lock mutex")
+            .push(RecordedQuery {
+                sql: sql.to_string(),
+                params,
+            });
+    }
+
+    fn next_result(&self) -> Option<MockResult> {
+        self.results
+            .lock()
+            .expect("could not lock mutex")
+            .pop_front()
+    }
+
+    /// record a query and take its scripted result
+    async fn run(&self, qry: &str, args: Vec<QueryArg>) -> Result<Option<MockResult>, JsError> {
+        let mut params = vec![];
+        for arg in args {
+            params.push(MockValue::from_query_arg(arg).await?);
+        }
+        self.record(qry, params);
+        match self.next_result() {
+            Some(MockResult::Error(msg)) => Err(JsError::new_string(msg)),
+            res => Ok(res),
+        }
+    }
+
+    /// execute a statement of insertMany, returns the rows affected
+    pub(crate) async fn execute_batch(
+        &self,
+        qry: &str,
+        args: Vec<QueryArg>,
+    ) -> Result<u64, JsError> {
+        Ok(match self.run(qry, args).await? {
+            Some(MockResult::Execute { rows_affected, .. }) => rows_affected,
+            Some(MockResult::Rows { rows, .. }) => rows.len() as u64,
+            _ => 0,
+        })
+    }
+}
+
+/// register a mock which scripts can use with getConnection(name)
+pub fn register_mock(name: &str, mock: Arc<MockDatabase>) {
+    register_connection(
+        name,
+        SqlxConnection::MockConnection {
+            con_str: format!("{REGISTERED_POOL_PREFIX}{name}"),
+            mock,
+        },
+    );
+}
+
+fn mock_row_facade(
+    row_mode: RowMode,
+    columns: &[String],
+    row: &[MockValue],
+) -> Result<JsValueFacade, JsError> {
+    let values = row
+        .iter()
+        .map(|v| v.to_js_value_facade())
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(match row_mode {
+        RowMode::Array => JsValueFacade::Array { val: values },
+        RowMode::Object => {
+            let mut obj: HashMap<String, JsValueFacade> = HashMap::new();
+            for (column, value) in columns.iter().zip(values) {
+                obj.insert(column.clone(), value);
+            }
+            JsValueFacade::Object { val: obj }
+        }
+    })
+}
+
+/// the onColumns metadata of scripted rows, the mock does not know types or nullability
+fn mock_columns(columns: &[String]) -> JsValueFacade {
+    JsValueFacade::Array {
+        val: columns
+            .iter()
+            .map(|name| {
+                let mut obj: HashMap<String, JsValueFacade> = HashMap::new();
+                obj.insert("name".to_string(), JsValueFacade::new_str(name));
+                obj.insert("type".to_string(), JsValueFacade::Null);
+                obj.insert("nullable".to_string(), JsValueFacade::Null);
+                JsValueFacade::Object { val: obj }
+            })
+            .collect(),
+    }
+}
+
+/// run a query or execute (if row_consumer_opt is None) on a mock
+pub(crate) async fn exe_query_mock(
+    mock: &MockDatabase,
+    qry: String,
+    args: Vec<QueryArg>,
+    row_consumer_opt: Option<JsValueFacade>,
+    mut options: QueryOptions,
+) -> Result<JsValueFacade, JsError> {
+    let observation = Observation::start(
+        std::mem::take(&mut options.observers),
+        mock.protocol(),
+        qry.as_str(),
+        args.len(),
+    );
+    let cancellation = std::mem::take(&mut options.cancellation);
+    let fut = exe_query_mock_inner(mock, qry, args, row_consumer_opt, options);
+    let res = cancellation.run(fut).await.and_then(|res| res);
+    if let Some(observation) = observation {
+        observation.finish(&res);
+    }
+    res
+}
+
+async fn exe_query_mock_inner(
+    mock: &MockDatabase,
+    qry: String,
+    args: Vec<QueryArg>,
+    row_consumer_opt: Option<JsValueFacade>,
+    options: QueryOptions,
+) -> Result<JsValueFacade, JsError> {
+    let result = mock.run(qry.as_str(), args).await?;
+
+    let (columns, rows) = match &result {
+        Some(MockResult::Rows { columns, rows }) => (columns.as_slice(), rows.as_slice()),
+        _ => (&[][..], &[][..]),
+    };
+
+    if let Some(on_columns) = &options.on_columns {
+        invoke_js_function(on_columns, vec![mock_columns(columns)]).await?;
+    }
+
+    if let Some(row_consumer) = &row_consumer_opt {
+        let mut ret_vec: Vec<JsValueFacade> = vec![];
+        for row in rows {
+            if row_consumer.is_null_or_undefined() {
+                ret_vec.push(mock_row_facade(options.row_mode, columns, row)?);
+            } else {
+                let consumer_args = match mock_row_facade(options.row_mode, columns, row)? {
+                    JsValueFacade::Array { val } => val,
+                    obj => vec![obj],
+                };
+                ret_vec.push(invoke_js_function(row_consumer, consumer_args).await?);
+            }
+        }
+        Ok(JsValueFacade::Array { val: ret_vec })
+    } else {
+        let (rows_affected, last_insert_id) = match &result {
+            Some(MockResult::Execute {
+                rows_affected,
+                last_insert_id,
+            }) => (*rows_affected, *last_insert_id),
+            _ => (rows.len() as u64, 0),
+        };
+        let mut returned_rows = vec![];
+        for row in rows {
+            returned_rows.push(mock_row_facade(options.row_mode, columns, row)?);
+        }
+        let mut obj: HashMap<String, JsValueFacade> = HashMap::new();
+        obj.insert(
+            "rowsAffected".to_string(),
+            JsValueFacade::new_f64(rows_affected as f64),
+        );
+        obj.insert(
+            "lastInsertId".to_string(),
+            JsValueFacade::new_f64(last_insert_id as f64),
+        );
+        obj.insert(
+            "rows".to_string(),
+            JsValueFacade::Array { val: returned_rows },
+        );
+        Ok(JsValueFacade::Object { val: obj })
+    }
+}
+
+/// run the query of a QueryStream on a mock
+pub(crate) async fn stream_query_mock(
+    mock: &MockDatabase,
+    qry: String,
+    args: Vec<QueryArg>,
+    options: QueryOptions,
+    sender: &Sender<RowResult>,
+) -> Result<(), JsError> {
+    let result = mock.run(qry.as_str(), args).await?;
+    if let Some(MockResult::Rows { columns, rows }) = result {
+        if let Some(on_columns) = &options.on_columns {
+            invoke_js_function(on_columns, vec![mock_columns(&columns)]).await?;
+        }
+        for row in rows {
+            let row_facade = mock_row_facade(options.row_mode, &columns, &row)?;
+            if sender.send(Ok(row_facade)).await.is_err() {
+                break;
+            }
+        }
+    } else if let Some(on_columns) = &options.on_columns {
+        invoke_js_function(on_columns, vec![mock_columns(&[])]).await?;
+    }
+    Ok(())
+}
@@ -0,0 +1,166 @@
+//! observers which are called for every query and execute of a Connection or Transaction
+//!
+//! observers are registered per runtime on the builder
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use green_copper_runtime::modules::db::sqlx::{add_query_observer, SlowQueryLogger};
+//! use quickjs_runtime::builder::QuickJsRuntimeBuilder;
+//! use std::time::Duration;
+//!
+//! let builder = green_copper_runtime::init_greco_rt(QuickJsRuntimeBuilder::new());
+//! let builder = add_query_observer(builder, SlowQueryLogger::new(Duration::from_millis(500)));
+//! let rt = builder.build();
+//! ```
+//!
+
+use crate::modules::db::sqlx::Protocol;
+use quickjs_runtime::builder::QuickJsRuntimeBuilder;
+use quickjs_runtime::jsutils::JsError;
+use quickjs_runtime::values::JsValueFacade;
+use std::cell::RefCell;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// a finished query or execute
+pub struct QueryEvent<'a> {
+    pub protocol: &'a Protocol,
+    /// the sql as sent to the database, so with positional parameters
+    pub sql: &'a str,
+    pub param_count: usize,
+    pub duration: Duration,
+    /// set for execute
+    pub rows_affected: Option<u64>,
+    /// the number of rows of a query or the rows produced by a RETURNING clause of an execute
+    pub rows_returned: Option<usize>,
+    pub error: Option<&'a JsError>,
+}
+
+pub trait SqlxQueryObserver: Send + Sync {
+    /// called from an async helper task after a query or execute finished
+    fn on_query(&self, event: &QueryEvent);
+}
+
+/// logs every query which took longer than the threshold as a warning
+pub struct SlowQueryLogger {
+    threshold: Duration,
+}
+
+impl SlowQueryLogger {
+    pub fn new(threshold: Duration) -> Self {
+        Self { threshold }
+    }
+}
+
+impl SqlxQueryObserver for SlowQueryLogger {
+    fn on_query(&self, event: &QueryEvent) {
+        if event.duration < self.threshold {
+            return;
+        }
+        match event.error {
+            None => log::warn!(
+                "slow {:?} query took {}ms ({} params): {}",
+                event.protocol,
+                event.duration.as_millis(),
+                event.param_count,
+                event.sql
+            ),
+            Some(err) => log::warn!(
+                "slow {:?} query failed after {}ms ({} params): {}, error: {}",
+                event.protocol,
+                event.duration.as_millis(),
+                event.param_count,
+                event.sql,
+                err
+            ),
+        }
+    }
+}
+
+thread_local! {
+    static OBSERVERS: RefCell<Vec<Arc<dyn SqlxQueryObserver>>> = RefCell::new(vec![]);
+}
+
+/// add an observer for the queries of the runtime built by builder
+pub fn add_query_observer<O: SqlxQueryObserver + 'static>(
+    builder: QuickJsRuntimeBuilder,
+    observer: O,
+) -> QuickJsRuntimeBuilder {
+    let observer: Arc<dyn SqlxQueryObserver> = Arc::new(observer);
+    builder.runtime_facade_init_hook(move |rt| {
+        rt.loop_sync_mut(move |_js_rt| {
+            OBSERVERS.with(|rc| rc.borrow_mut().push(observer));
+        });
+        Ok(())
+    })
+}
+
+/// the observers of the current runtime, should be called from the runtime thread
+pub(crate) fn observers() -> Vec<Arc<dyn SqlxQueryObserver>> {
+    OBSERVERS.with(|rc| rc.borrow().clone())
+}
+
+/// a running query which is reported to the observers when finished
+pub(crate) struct Observation {
+    observers: Vec<Arc<dyn SqlxQueryObserver>>,
+    protocol: Protocol,
+    sql: String,
+    param_count: usize,
+    start: Instant,
+}
+
+impl Observation {
+    /// returns None if there are no observers
+    pub(crate) fn start(
+        observers: Vec<Arc<dyn SqlxQueryObserver>>,
+        protocol: Protocol,
+        sql: &str,
+        param_count: usize,
+    ) -> Option<Self> {
+        if observers.is_empty() {
+            return None;
+        }
+        Some(Self {
+            observers,
+            protocol,
+            sql: sql.to_string(),
+            param_count,
+            start: Instant::now(),
+        })
+    }
+
+    pub(crate) fn finish(self, res: &Result<JsValueFacade, JsError>) {
+        let duration = self.start.elapsed();
+        let mut rows_affected = None;
+        let mut rows_returned = None;
+        match res {
+            // query
+            Ok(JsValueFacade::Array { val }) => {
+                rows_returned = Some(val.len());
+            }
+            // execute
+            Ok(JsValueFacade::Object { val }) => {
+                if let Some(JsValueFacade::F64 { val }) = val.get("rowsAffected") {
+                    rows_affected = Some(*val as u64);
+                }
+                if let Some(JsValueFacade::Array { val }) = val.get("rows") {
+                    rows_returned = Some(val.len());
+                }
+            }
+            _ => {}
+        }
+        let event = QueryEvent {
+            protocol: &self.protocol,
+            sql: self.sql.as_str(),
+            param_count: self.param_count,
+            duration,
+            rows_affected,
+            rows_returned,
+            error: res.as_ref().err(),
+        };
+        for observer in &self.observers {
+            observer.on_query(&event);
+        }
+    }
+}
@@ -0,0 +1,271 @@
+//! connection options for connect / connectMySql / connectPostgres
+//!
+//! # Example
+//!
+//! ```javascript
+//! async function test() {
+//!     let sqlxMod = await import('greco://sqlx');
+//!     let con = await sqlxMod.connectPostgres('db.local', 5432, 'user', 'pass', 'mydb', {
+//!         maxConnections: 8,
+//!         minConnections: 1,
+//!         acquireTimeoutMs: 5000,
+//!         idleTimeoutMs: 60000,
+//!         // null means connections are never closed because of their age
+//!         maxLifetimeMs: null,
+//!         sslMode: 'verify-full',
+//!         sslCa: '/etc/ssl/db-ca.pem',
+//!         sslCert: '/etc/ssl/client.pem',
+//!         sslKey: '/etc/ssl/client.key',
+//!         isolationLevel: 'repeatable read',
+//!         applicationName: 'edge-sync',
+//!         charset: 'UTF8',
+//!         afterConnect: ["SET TIME ZONE 'UTC'"]
+//!     });
+//! }
+//! ```
+//!
+//! # Options
+//!
+//! ##maxConnections: number (default 64, at least 1)
+//! ##minConnections: number (default 2, not greater than maxConnections)
+//! ##acquireTimeoutMs: number (default 10000)
+//! ##idleTimeoutMs: number | null (default 360000)
+//! ##maxLifetimeMs: number | null (default 3600000)
+//! ##sslMode: string (default 'disabled' for mysql and 'disable' for postgres, for connect the sslmode / ssl-mode of the url)
+//! mysql: disabled, preferred, required, verify_ca, verify_identity
+//! postgres: disable, allow, prefer, require, verify-ca, verify-full
+//! ##sslCa: string, path to a pem file with the CA certificate(s)
+//! ##sslCert: string, path to a pem file with the client certificate
+//! ##sslKey: string, path to a pem file with the client key
+//! ##isolationLevel: string | null (default 'read committed')
+//! one of 'read uncommitted', 'read committed', 'repeatable read', 'serializable', null keeps the server default
+//! ##applicationName: string (postgres only)
+//! ##charset: string
+//! ##afterConnect: Array<string>, statements which are executed for every new connection in the pool
+//!
+
+use quickjs_runtime::jsutils::JsError;
+use quickjs_runtime::quickjsrealmadapter::QuickJsRealmAdapter;
+use quickjs_runtime::quickjsvalueadapter::QuickJsValueAdapter;
+use std::time::Duration;
+
+/// options for a connection pool, the pools are shared per connection string and options
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct SqlxConnectionOptions {
+    pub max_connections: u32,
+    pub min_connections: u32,
+    pub acquire_timeout: Duration,
+    pub idle_timeout: Option<Duration>,
+    pub max_lifetime: Option<Duration>,
+    pub ssl_mode: Option<String>,
+    pub ssl_ca: Option<String>,
+    pub ssl_client_cert: Option<String>,
+    pub ssl_client_key: Option<String>,
+    pub isolation_level: Option<String>,
+    pub application_name: Option<String>,
+    pub charset: Option<String>,
+    pub after_connect: Vec<String>,
+}
+
+impl Default for SqlxConnectionOptions {
+    fn default() -> Self {
+        Self {
+            max_connections: 64,
+            min_connections: 2,
+            acquire_timeout: Duration::from_secs(10),
+            idle_timeout: Some(Duration::from_secs(360)),
+            max_lifetime: Some(Duration::from_secs(3600)),
+            ssl_mode: None,
+            ssl_ca: None,
+            ssl_client_cert: None,
+            ssl_client_key: None,
+            isolation_level: Some("read committed".to_string()),
+            application_name: None,
+            charset: None,
+            after_connect: vec![],
+        }
+    }
+}
+
+impl SqlxConnectionOptions {
+    /// parse an options object as passed to connect / connectMySql / connectPostgres
+    pub(crate) fn parse(
+        realm: &QuickJsRealmAdapter,
+        options: &QuickJsValueAdapter,
+    ) -> Result<Self, JsError> {
+        let mut res = Self::default();
+        if options.is_null_or_undefined() {
+            return Ok(res);
+        }
+        if !options.is_object() {
+            return Err(JsError::new_str("connection options should be an object"));
+        }
+
+        if let Some(val) = count_option(realm, options, "maxConnections")? {
+            if val < 1 {
+                return Err(JsError::new_str("maxConnections should be at least 1"));
+            }
+            res.max_connections = val;
+        }
+        if let Some(val) = count_option(realm, options, "minConnections")? {
+            res.min_connections = val;
+        }
+        if res.min_connections > res.max_connections {
+            return Err(JsError::new_str(
+                "minConnections should not be greater than maxConnections",
+            ));
+        }
+        if let Some(val) = millis_option(realm, options, "acquireTimeoutMs")? {
+            res.acquire_timeout = val;
+        }
+        res.idle_timeout = duration_option(realm, options, "idleTimeoutMs", res.idle_timeout)?;
+        res.max_lifetime = duration_option(realm, options, "maxLifetimeMs", res.max_lifetime)?;
+
+        res.ssl_mode = string_option(realm, options, "sslMode")?;
+        res.ssl_ca = string_option(realm, options, "sslCa")?;
+        res.ssl_client_cert = string_option(realm, options, "sslCert")?;
+        res.ssl_client_key = string_option(realm, options, "sslKey")?;
+
+        let isolation_level = realm.get_object_property(options, "isolationLevel")?;
+        if isolation_level.is_null() {
+            res.isolation_level = None;
+        } else if isolation_level.is_string() {
+            let level = isolation_level.to_string()?.to_lowercase();
+            match level.as_str() {
+                "read uncommitted" | "read committed" | "repeatable read" | "serializable" => {
+                    res.isolation_level = Some(level);
+                }
+                _ => {
+                    return Err(JsError::new_string(format!(
+                        "unsupported isolationLevel: {level}"
+                    )));
+                }
+            }
+        } else if !isolation_level.is_undefined() {
+            return Err(JsError::new_str(
+                "isolationLevel should be a string or null",
+            ));
+        }
+
+        res.application_name = string_option(realm, options, "applicationName")?;
+        res.charset = string_option(realm, options, "charset")?;
+
+        let after_connect = realm.get_object_property(options, "afterConnect")?;
+        if after_connect.is_array() {
+            for x in 0..realm.get_array_length(&after_connect)? {
+                let stmt = realm.get_array_element(&after_connect, x)?;
+                if !stmt.is_string() {
+                    return Err(JsError::new_str(
+                        "afterConnect should be an Array of strings",
+                    ));
+                }
+                res.after_connect.push(stmt.to_string()?);
+            }
+        } else if !after_connect.is_null_or_undefined() {
+            return Err(JsError::new_str(
+                "afterConnect should be an Array of strings",
+            ));
+        }
+
+        Ok(res)
+    }
+
+    /// the statements to execute for every new connection, the isolation level first
+    pub(crate) fn after_connect_statements(&self, protocol_type: &str) -> Vec<String> {
+        let mut res = vec![];
+        if let Some(level) = &self.isolation_level {
+            if protocol_type.eq("mysql") {
+                res.push(format!(
+                    "SET SESSION TRANSACTION ISOLATION LEVEL {}",
+                    level.to_uppercase()
+                ));
+            } else {
+                res.push(format!("SET default_transaction_isolation TO '{level}'"));
+            }
+        }
+        res.extend(self.after_connect.iter().cloned());
+        res
+    }
+}
+
+fn number_option(
+    realm: &QuickJsRealmAdapter,
+    options: &QuickJsValueAdapter,
+    name: &str,
+) -> Result<Option<f64>, JsError> {
+    let val = realm.get_object_property(options, name)?;
+    if val.is_i32() {
+        Ok(Some(val.to_i32() as f64))
+    } else if val.is_f64() {
+        Ok(Some(val.to_f64()))
+    } else if val.is_undefined() {
+        Ok(None)
+    } else {
+        Err(JsError::new_string(format!("{name} should be a number")))
+    }
+}
+
+/// a number option which should be a non negative integer
+fn count_option(
+    realm: &QuickJsRealmAdapter,
+    options: &QuickJsValueAdapter,
+    name: &str,
+) -> Result<Option<u32>, JsError> {
+    match number_option(realm, options, name)? {
+        Some(val) if val.fract() != 0.0 || !(0.0..=u32::MAX as f64).contains(&val) => Err(
+            JsError::new_string(format!("{name} should be a non negative integer")),
+        ),
+        Some(val) => Ok(Some(val as u32)),
+        None => Ok(None),
+    }
+}
+
+/// undefined keeps the default, null or 0 disables the timeout
+fn duration_option(
+    realm: &QuickJsRealmAdapter,
+    options: &QuickJsValueAdapter,
+    name: &str,
+    default: Option<Duration>,
+) -> Result<Option<Duration>, JsError> {
+    let val = realm.get_object_property(options, name)?;
+    if val.is_null() {
+        return Ok(None);
+    }
+    if val.is_undefined() {
+        return Ok(default);
+    }
+    match millis_option(realm, options, name)? {
+        Some(duration) if !duration.is_zero() => Ok(Some(duration)),
+        _ => Ok(None),
+    }
+}
+
+/// a number option in milliseconds which should be finite and not negative
+fn millis_option(
+    realm: &QuickJsRealmAdapter,
+    options: &QuickJsValueAdapter,
+    name: &str,
+) -> Result<Option<Duration>, JsError> {
+    match number_option(realm, options, name)? {
+        Some(val) if !val.is_finite() || val < 0.0 => Err(JsError::new_string(format!(
+            "{name} should be a non negative number"
+        ))),
+        Some(val) => Ok(Some(Duration::from_millis(val as u64))),
+        None => Ok(None),
+    }
+}
+
+fn string_option(
+    realm: &QuickJsRealmAdapter,
+    options: &QuickJsValueAdapter,
+    name: &str,
+) -> Result<Option<String>, JsError> {
+    let val = realm.get_object_property(options, name)?;
+    if val.is_string() {
+        Ok(Some(val.to_string()?))
+    } else if val.is_null_or_undefined() {
+        Ok(None)
+    } else {
+        Err(JsError::new_string(format!("{name} should be a string")))
+    }
+}
@@ -0,0 +1,206 @@
+//! rewriting of named parameters (:name) to the positional parameters of the database
+//!
+//! the query is scanned as SQL so a colon in a string literal, a quoted identifier, a comment, a
+//! dollar quoted body (postgres) or a cast (::int) is left alone
+//!
+//! on postgres a name which is used more than once is rewritten to the same $n, on mysql and sqlite
+//! every occurrence becomes a ? and the value is bound once for every occurrence
+//!
+
+use crate::modules::db::sqlx::{ParsedQuery, Protocol};
+
+fn is_ident_start(c: char) -> bool {
+    c.is_ascii_alphabetic() || c == '_'
+}
+
+fn is_ident_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+/// copy a quoted string or identifier, returns the index after the closing quote
+/// a doubled quote is an escaped quote, backslash escapes are honoured if backslash_escapes is true
+fn copy_quoted(
+    chars: &[char],
+    start: usize,
+    quote: char,
+    backslash_escapes: bool,
+    out: &mut String,
+) -> usize {
+    out.push(quote);
+    let mut i = start + 1;
+    while i < chars.len() {
+        let c = chars[i];
+        out.push(c);
+        i += 1;
+        if c == '\\' && backslash_escapes {
+            if i < chars.len() {
+                out.push(chars[i]);
+                i += 1;
+            }
+        } else if c == quote {
+            if i < chars.len() && chars[i] == quote {
+                out.push(quote);
+                i += 1;
+            } else {
+                return i;
+            }
+        }
+    }
+    i
+}
+
+/// copy a comment up to and including the end of the line
+fn copy_line_comment(chars: &[char], start: usize, out: &mut String) -> usize {
+    let mut i = start;
+    while i < chars.len() {
+        out.push(chars[i]);
+        i += 1;
+        if chars[i - 1] == '\n' {
+            break;
+        }
+    }
+    i
+}
+
+/// copy a block comment, postgres allows nested block comments
+fn copy_block_comment(chars: &[char], start: usize, nested: bool, out: &mut String) -> usize {
+    let mut depth = 0;
+    let mut i = start;
+    while i < chars.len() {
+        if chars[i] == '/' && chars.get(i + 1) == Some(&'*') && (nested || depth == 0) {
+            depth += 1;
+            out.push_str("/*");
+            i += 2;
+        } else if chars[i] == '*' && chars.get(i + 1) == Some(&'/') {
+            depth -= 1;
+            out.push_str("*/");
+            i += 2;
+            if depth == 0 {
+                break;
+            }
+        } else {
+            out.push(chars[i]);
+            i += 1;
+        }
+    }
+    i
+}
+
+/// get the tag of a dollar quote ($$ or $tag$) which starts at start
+fn dollar_tag(chars: &[char], start: usize) -> Option<String> {
+    let mut i = start + 1;
+    if i < chars.len() && is_ident_start(chars[i]) {
+        while i < chars.len() && is_ident_char(chars[i]) {
+            i += 1;
+        }
+    }
+    if i < chars.len() && chars[i] == '$' {
+        Some(chars[start..=i].iter().collect())
+    } else {
+        None
+    }
+}
+
+/// copy a dollar quoted string, returns the index after the closing tag
+fn copy_dollar_quoted(chars: &[char], start: usize, tag: &str, out: &mut String) -> usize {
+    let tag_chars: Vec<char> = tag.chars().collect();
+    out.push_str(tag);
+    let mut i = start + tag_chars.len();
+    while i < chars.len() {
+        if chars[i..].starts_with(&tag_chars) {
+            out.push_str(tag);
+            return i + tag_chars.len();
+        }
+        out.push(chars[i]);
+        i += 1;
+    }
+    i
+}
+
+/// rewrite the named parameters of a query to positional parameters
+pub(crate) fn rewrite_named_params(protocol: &Protocol, query: &str) -> ParsedQuery {
+    let chars: Vec<char> = query.chars().collect();
+    let mut actual_query = String::with_capacity(query.len());
+    let mut args_names_in_order: Vec<String> = vec![];
+
+    let is_mysql = matches!(protocol, Protocol::MySql);
+    let is_postgres = matches!(protocol, Protocol::Postgres);
+
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let next = chars.get(i + 1).copied();
+        match c {
+            '\'' => {
+                // E'..' strings on postgres and all strings on mysql allow backslash escapes
+                let escape_string = is_postgres
+                    && i > 0
+                    && (chars[i - 1] == 'E' || chars[i - 1] == 'e')
+                    && (i < 2 || !is_ident_char(chars[i - 2]));
+                i = copy_quoted(&chars, i, c, is_mysql || escape_string, &mut actual_query);
+            }
+            '"' => {
+                i = copy_quoted(&chars, i, c, is_mysql, &mut actual_query);
+            }
+            '`' if !is_postgres => {
+                i = copy_quoted(&chars, i, c, false, &mut actual_query);
+            }
+            '-' if next == Some('-') => {
+                i = copy_line_comment(&chars, i, &mut actual_query);
+            }
+            '#' if is_mysql => {
+                i = copy_line_comment(&chars, i, &mut actual_query);
+            }
+            '/' if next == Some('*') => {
+                i = copy_block_comment(&chars, i, is_postgres, &mut actual_query);
+            }
+            '$' if is_postgres && (i == 0 || !is_ident_char(chars[i - 1])) => {
+                if let Some(tag) = dollar_tag(&chars, i) {
+                    i = copy_dollar_quoted(&chars, i, tag.as_str(), &mut actual_query);
+                } else {
+                    actual_query.push(c);
+                    i += 1;
+                }
+            }
+            ':' if next == Some(':') => {
+                // a cast
+                actual_query.push_str("::");
+                i += 2;
+            }
+            ':' if next.map(is_ident_start).unwrap_or(false) => {
+                let mut end = i + 1;
+                while end < chars.len() && is_ident_char(chars[end]) {
+                    end += 1;
+                }
+                let param_name: String = chars[i + 1..end].iter().collect();
+                if is_postgres {
+                    let idx = match args_names_in_order.iter().position(|n| n.eq(&param_name)) {
+                        Some(idx) => idx,
+                        None => {
+                            args_names_in_order.push(param_name);
+                            args_names_in_order.len() - 1
+                        }
+                    };
+                    actual_query.push_str(format!("${}", idx + 1).as_str());
+                } else {
+                    args_names_in_order.push(param_name);
+                    actual_query.push('?');
+                }
+                i = end;
+            }
+            _ => {
+                actual_query.push(c);
+                i += 1;
+            }
+        }
+    }
+
+    ParsedQuery {
+        actual_query,
+        args_names_in_order: if args_names_in_order.is_empty() {
+            None
+        } else {
+            Some(args_names_in_order)
+        },
+    }
+}
@@ -0,0 +1,549 @@
+//! mapping of Postgres types to and from script values
+//!
+//! # Results
+//!
+//! | Postgres | script |
+//! |---|---|
+//! | BOOL | boolean |
+//! | INT2, INT4, "CHAR" | number |
+//! | INT8, FLOAT4, FLOAT8 | number |
+//! | NUMERIC | string, so no precision is lost, including 'NaN', 'Infinity' and '-Infinity' |
+//! | VARCHAR, TEXT, NAME, CITEXT, enums | string |
+//! | UUID | string (uppercase) |
+//! | DATE, TIME | string |
+//! | TIMESTAMP | string, e.g. '2024-01-31T13:45:00.000000' |
+//! | TIMESTAMPTZ | string, e.g. '2024-01-31T13:45:00.123Z' (UTC) |
+//! | INTERVAL | {months: number, days: number, microseconds: number} |
+//! | JSON, JSONB | the parsed value |
+//! | INET, CIDR | string, e.g. '10.0.0.1' or '10.0.0.0/8' |
+//! | BYTEA | Uint8Array |
+//! | arrays of the above | Array, NULL elements are null |
+//!
+//! # Parameters
+//!
+//! a Date is bound as TIMESTAMPTZ, an Array is bound as an array of the type of its first element
+//! which is not null (number, string, boolean, Date, object as JSONB or Uint8Array as BYTEA)
+//!
+
+use crate::modules::db::sqlx::{date_time_arg, QueryArg};
+use quickjs_runtime::jsutils::JsError;
+use quickjs_runtime::values::{JsValueFacade, TypedArrayType};
+use sqlx_lib::error::BoxDynError;
+use sqlx_lib::postgres::types::{Oid, PgInterval};
+use sqlx_lib::postgres::{
+    PgArguments, PgHasArrayType, PgRow, PgTypeInfo, PgTypeKind, PgValueFormat, PgValueRef,
+};
+use sqlx_lib::query::Query;
+use sqlx_lib::types::ipnetwork::IpNetwork;
+use sqlx_lib::types::time::format_description::well_known::Rfc3339;
+use sqlx_lib::types::time::format_description::FormatItem;
+use sqlx_lib::types::time::{Date, OffsetDateTime, PrimitiveDateTime, Time};
+use sqlx_lib::{Column, Decode, Postgres, Row, Type, TypeInfo};
+use std::collections::HashMap;
+
+lazy_static! {
+    static ref TIMESTAMP_FORMAT: Vec<FormatItem<'static>> =
+        sqlx_lib::types::time::format_description::parse(
+            "[year]-[month]-[day]T[hour]:[minute]:[second].[subsecond digits:6]"
+        )
+        .expect("invalid timestamp format");
+}
+
+/// get a nullable column and map its value
+fn pg_get<'r, T, M>(row: &'r PgRow, x: usize, mapper: M) -> Result<JsValueFacade, JsError>
+where
+    T: Decode<'r, Postgres> + Type<Postgres>,
+    M: Fn(T) -> Result<JsValueFacade, JsError>,
+{
+    let v_opt: Option<T> = row
+        .try_get(x)
+        .map_err(|e| JsError::new_string(format!("{e:?}")))?;
+    match v_opt {
+        None => Ok(JsValueFacade::Null),
+        Some(v) => mapper(v),
+    }
+}
+
+/// get a nullable array column and map its elements, NULL elements are mapped to null
+fn pg_get_array<T, M>(row: &PgRow, x: usize, mapper: M) -> Result<JsValueFacade, JsError>
+where
+    T: for<'a> Decode<'a, Postgres> + Type<Postgres> + PgHasArrayType,
+    M: Fn(T) -> Result<JsValueFacade, JsError>,
+{
+    let v_opt: Option<Vec<Option<T>>> = row
+        .try_get(x)
+        .map_err(|e| JsError::new_string(format!("{e:?}")))?;
+    match v_opt {
+        None => Ok(JsValueFacade::Null),
+        Some(v) => {
+            let mut val = vec![];
+            for e in v {
+                val.push(match e {
+                    None => JsValueFacade::Null,
+                    Some(e) => mapper(e)?,
+                });
+            }
+            Ok(JsValueFacade::Array { val })
+        }
+    }
+}
+
+fn bool_value(v: bool) -> Result<JsValueFacade, JsError> {
+    Ok(JsValueFacade::new_bool(v))
+}
+
+fn i16_value(v: i16) -> Result<JsValueFacade, JsError> {
+    Ok(JsValueFacade::new_i32(v as i32))
+}
+
+fn i32_value(v: i32) -> Result<JsValueFacade, JsError> {
+    Ok(JsValueFacade::new_i32(v))
+}
+
+fn i64_value(v: i64) -> Result<JsValueFacade, JsError> {
+    Ok(JsValueFacade::new_f64(v as f64))
+}
+
+fn f32_value(v: f32) -> Result<JsValueFacade, JsError> {
+    Ok(JsValueFacade::new_f64(v as f64))
+}
+
+fn f64_value(v: f64) -> Result<JsValueFacade, JsError> {
+    Ok(JsValueFacade::new_f64(v))
+}
+
+fn string_value(v: String) -> Result<JsValueFacade, JsError> {
+    Ok(JsValueFacade::new_string(v))
+}
+
+/// a NUMERIC as its exact string, rust_decimal can not hold NaN, the infinities or more than 28
+/// significant digits
+struct PgNumericString(String);
+
+const NUMERIC_POS: u16 = 0x0000;
+const NUMERIC_NEG: u16 = 0x4000;
+const NUMERIC_NAN: u16 = 0xC000;
+const NUMERIC_PINF: u16 = 0xD000;
+const NUMERIC_NINF: u16 = 0xF000;
+
+impl Type<Postgres> for PgNumericString {
+    fn type_info() -> PgTypeInfo {
+        PgTypeInfo::with_oid(Oid(1700))
+    }
+}
+
+impl PgHasArrayType for PgNumericString {
+    fn array_type_info() -> PgTypeInfo {
+        PgTypeInfo::with_oid(Oid(1231))
+    }
+}
+
+impl<'r> Decode<'r, Postgres> for PgNumericString {
+    fn decode(value: PgValueRef<'r>) -> Result<Self, BoxDynError> {
+        match value.format() {
+            PgValueFormat::Binary => Ok(Self(decode_numeric(value.as_bytes()?)?)),
+            PgValueFormat::Text => Ok(Self(value.as_str()?.to_string())),
+        }
+    }
+}
+
+/// format the binary representation of a NUMERIC like numeric_out in Postgres does, the value is
+/// a header of ndigits, weight, sign and dscale followed by ndigits base 10000 digits
+fn decode_numeric(buf: &[u8]) -> Result<String, BoxDynError> {
+    if buf.len() < 8 {
+        return Err("invalid NUMERIC value".into());
+    }
+    let read_u16 = |idx: usize| u16::from_be_bytes([buf[idx], buf[idx + 1]]);
+    let ndigits = read_u16(0) as usize;
+    let weight = read_u16(2) as i16 as i32;
+    let sign = read_u16(4);
+    let dscale = read_u16(6) as usize;
+    if buf.len() != 8 + ndigits * 2 {
+        return Err("invalid NUMERIC value".into());
+    }
+    let digits: Vec<u16> = (0..ndigits).map(|i| read_u16(8 + i * 2)).collect();
+    // the digit with weight w is at index weight - w
+    let digit = |idx: i32| {
+        if idx >= 0 && (idx as usize) < digits.len() {
+            digits[idx as usize]
+        } else {
+            0
+        }
+    };
+
+    let mut res = String::new();
+    match sign {
+        NUMERIC_NAN => return Ok("NaN".to_string()),
+        NUMERIC_PINF => return Ok("Infinity".to_string()),
+        NUMERIC_NINF => return Ok("-Infinity".to_string()),
+        NUMERIC_NEG => res.push('-'),
+        NUMERIC_POS => {}
+        _ => return Err(format!("invalid NUMERIC sign {sign:#x}").into()),
+    }
+    if weight < 0 {
+        res.push('0');
+    } else {
+        for idx in 0..=weight {
+            if idx == 0 {
+                res.push_str(digit(idx).to_string().as_str());
+            } else {
+                res.push_str(format!("{:04}", digit(idx)).as_str());
+            }
+        }
+    }
+    if dscale > 0 {
+        let mut fraction = String::new();
+        let mut idx = weight + 1;
+        while fraction.len() < dscale {
+            fraction.push_str(format!("{:04}", digit(idx)).as_str());
+            idx += 1;
+        }
+        fraction.truncate(dscale);
+        res.push('.');
+        res.push_str(fraction.as_str());
+    }
+    Ok(res)
+}
+
+fn numeric_value(v: PgNumericString) -> Result<JsValueFacade, JsError> {
+    Ok(JsValueFacade::new_string(v.0))
+}
+
+fn uuid_value(v: uuid::Uuid) -> Result<JsValueFacade, JsError> {
+    Ok(JsValueFacade::new_string(
+        v.to_string().to_ascii_uppercase(),
+    ))
+}
+
+fn date_value(v: Date) -> Result<JsValueFacade, JsError> {
+    Ok(JsValueFacade::new_string(v.to_string()))
+}
+
+fn time_value(v: Time) -> Result<JsValueFacade, JsError> {
+    Ok(JsValueFacade::new_string(v.to_string()))
+}
+
+fn timestamp_value(v: PrimitiveDateTime) -> Result<JsValueFacade, JsError> {
+    let formatted = v
+        .format(&TIMESTAMP_FORMAT)
+        .map_err(|e| JsError::new_string(format!("{e}")))?;
+    Ok(JsValueFacade::new_string(formatted))
+}
+
+fn timestamptz_value(v: OffsetDateTime) -> Result<JsValueFacade, JsError> {
+    let formatted = v
+        .format(&Rfc3339)
+        .map_err(|e| JsError::new_string(format!("{e}")))?;
+    Ok(JsValueFacade::new_string(formatted))
+}
+
+fn interval_value(v: PgInterval) -> Result<JsValueFacade, JsError> {
+    let mut val = HashMap::new();
+    val.insert("months".to_string(), JsValueFacade::new_i32(v.months));
+    val.insert("days".to_string(), JsValueFacade::new_i32(v.days));
+    val.insert(
+        "microseconds".to_string(),
+        JsValueFacade::new_f64(v.microseconds as f64),
+    );
+    Ok(JsValueFacade::Object { val })
+}
+
+fn json_value(value: serde_json::Value) -> Result<JsValueFacade, JsError> {
+    Ok(JsValueFacade::SerdeValue { value })
+}
+
+/// an INET with a full length prefix is just a host address
+fn inet_value(v: IpNetwork) -> Result<JsValueFacade, JsError> {
+    let host_prefix = if v.is_ipv4() { 32 } else { 128 };
+    if v.prefix() == host_prefix {
+        Ok(JsValueFacade::new_string(v.ip().to_string()))
+    } else {
+        Ok(JsValueFacade::new_string(v.to_string()))
+    }
+}
+
+fn cidr_value(v: IpNetwork) -> Result<JsValueFacade, JsError> {
+    Ok(JsValueFacade::new_string(v.to_string()))
+}
+
+fn bytes_value(buffer: Vec<u8>) -> Result<JsValueFacade, JsError> {
+    Ok(JsValueFacade::TypedArray {
+        buffer,
+        array_type: TypedArrayType::Uint8,
+    })
+}
+
+/// map a Postgres row to the values which are passed to script
+pub(crate) fn pg_row_values(row: &PgRow) -> Result<Vec<JsValueFacade>, JsError> {
+    let mut row_args_vec: Vec<JsValueFacade> = vec![];
+    for x in 0..row.len() {
+        let column = row.column(x);
+        let pg_type = column.type_info();
+        log::trace!("COL TYPE {} isnull:{}", pg_type.name(), pg_type.is_null());
+
+        // see https://docs.rs/sqlx/latest/sqlx/postgres/types/index.html
+        let jsvf = match pg_type.name() {
+            "BOOL" => pg_get(row, x, bool_value)?,
+            "BOOL[]" => pg_get_array(row, x, bool_value)?,
+            "INT2" => pg_get(row, x, i16_value)?,
+            "INT2[]" => pg_get_array(row, x, i16_value)?,
+            "\"CHAR\"" => pg_get(row, x, |v: i8| Ok(JsValueFacade::new_i32(v as i32)))?,
+            "INT4" => pg_get(row, x, i32_value)?,
+            "INT4[]" => pg_get_array(row, x, i32_value)?,
+            "INT8" => pg_get(row, x, i64_value)?,
+            "INT8[]" => pg_get_array(row, x, i64_value)?,
+            "FLOAT4" => pg_get(row, x, f32_value)?,
+            "FLOAT4[]" => pg_get_array(row, x, f32_value)?,
+            "FLOAT8" => pg_get(row, x, f64_value)?,
+            "FLOAT8[]" => pg_get_array(row, x, f64_value)?,
+            "NUMERIC" => pg_get(row, x, numeric_value)?,
+            "NUMERIC[]" => pg_get_array(row, x, numeric_value)?,
+            "VARCHAR" | "CHAR" | "TEXT" | "NAME" | "CITEXT" => pg_get(row, x, string_value)?,
+            "VARCHAR[]" | "CHAR[]" | "TEXT[]" | "NAME[]" | "CITEXT[]" => {
+                pg_get_array(row, x, string_value)?
+            }
+            "UUID" => pg_get(row, x, uuid_value)?,
+            "UUID[]" => pg_get_array(row, x, uuid_value)?,
+            "DATE" => pg_get(row, x, date_value)?,
+            "DATE[]" => pg_get_array(row, x, date_value)?,
+            "TIME" => pg_get(row, x, time_value)?,
+            "TIME[]" => pg_get_array(row, x, time_value)?,
+            "TIMESTAMP" => pg_get(row, x, timestamp_value)?,
+            "TIMESTAMP[]" => pg_get_array(row, x, timestamp_value)?,
+            "TIMESTAMPTZ" => pg_get(row, x, timestamptz_value)?,
+            "TIMESTAMPTZ[]" => pg_get_array(row, x, timestamptz_value)?,
+            "INTERVAL" => pg_get(row, x, interval_value)?,
+            "INTERVAL[]" => pg_get_array(row, x, interval_value)?,
+            "JSON" | "JSONB" => pg_get(row, x, json_value)?,
+            "JSON[]" | "JSONB[]" => pg_get_array(row, x, json_value)?,
+            "INET" => pg_get(row, x, inet_value)?,
+            "INET[]" => pg_get_array(row, x, inet_value)?,
+            "CIDR" => pg_get(row, x, cidr_value)?,
+            "CIDR[]" => pg_get_array(row, x, cidr_value)?,
+            "BYTEA" => pg_get(row, x, bytes_value)?,
+            "BYTEA[]" => pg_get_array(row, x, bytes_value)?,
+            "NULL" => JsValueFacade::Null,
+            &_ => match pg_type.kind() {
+                // enums are sent as text, sqlx only checks the type for declared Rust enums
+                PgTypeKind::Enum(_) => {
+                    let v_opt: Option<String> = row
+                        .try_get_unchecked(x)
+                        .map_err(|e| JsError::new_string(format!("{e:?}")))?;
+                    match v_opt {
+                        None => JsValueFacade::Null,
+                        Some(v) => JsValueFacade::new_string(v),
+                    }
+                }
+                PgTypeKind::Array(element) if matches!(element.kind(), PgTypeKind::Enum(_)) => {
+                    let v_opt: Option<Vec<Option<String>>> = row
+                        .try_get_unchecked(x)
+                        .map_err(|e| JsError::new_string(format!("{e:?}")))?;
+                    match v_opt {
+                        None => JsValueFacade::Null,
+                        Some(v) => JsValueFacade::Array {
+                            val: v
+                                .into_iter()
+                                .map(|e| match e {
+                                    None => JsValueFacade::Null,
+                                    Some(e) => JsValueFacade::new_string(e),
+                                })
+                                .collect(),
+                        },
+                    }
+                }
+                _ => {
+                    log::error!(
+                        "COL {} TYPE {} isnull:{}",
+                        column.name(),
+                        pg_type.name(),
+                        pg_type.is_null()
+                    );
+                    JsValueFacade::Null
+                }
+            },
+        };
+        row_args_vec.push(jsvf);
+    }
+    Ok(row_args_vec)
+}
+
+/// collect the elements of an Array argument, null and undefined elements become None
+fn array_elements<T, C>(items: Vec<QueryArg>, convert: C) -> Result<Vec<Option<T>>, JsError>
+where
+    C: Fn(QueryArg) -> Option<T>,
+{
+    let mut res = vec![];
+    for item in items {
+        match item {
+            QueryArg::Value(JsValueFacade::Null) | QueryArg::Value(JsValueFacade::Undefined) => {
+                res.push(None);
+            }
+            item => match convert(item) {
+                Some(v) => res.push(Some(v)),
+                None => {
+                    return Err(JsError::new_str(
+                        "all elements of an Array argument should be of the same type",
+                    ));
+                }
+            },
+        }
+    }
+    Ok(res)
+}
+
+/// bind an Array argument as a Postgres array, the element type is determined by the first
+/// element which is not null
+pub(crate) async fn bind_pg_array<'q>(
+    qry_obj: Query<'q, Postgres, PgArguments>,
+    items: Vec<QueryArg>,
+) -> Result<Query<'q, Postgres, PgArguments>, JsError> {
+    let first = items.iter().find(|item| {
+        !matches!(
+            item,
+            QueryArg::Value(JsValueFacade::Null) | QueryArg::Value(JsValueFacade::Undefined)
+        )
+    });
+    match first {
+        None => {
+            let val: Vec<Option<String>> = items.iter().map(|_| None).collect();
+            Ok(qry_obj.bind(val))
+        }
+        Some(QueryArg::Array(_)) => Err(JsError::new_str(
+            "nested Arrays are not supported as arguments",
+        )),
+        Some(QueryArg::Date(_)) => {
+            let mut val: Vec<Option<OffsetDateTime>> = vec![];
+            for item in array_elements(items, |item| match item {
+                QueryArg::Date(millis) => Some(millis),
+                _ => None,
+            })? {
+                val.push(match item {
+                    None => None,
+                    Some(millis) => Some(date_time_arg(millis)?),
+                });
+            }
+            Ok(qry_obj.bind(val))
+        }
+        Some(QueryArg::Value(JsValueFacade::I32 { .. }))
+        | Some(QueryArg::Value(JsValueFacade::F64 { .. })) => {
+            let all_i32 = items.iter().all(|item| {
+                matches!(
+                    item,
+                    QueryArg::Value(JsValueFacade::I32 { .. })
+                        | QueryArg::Value(JsValueFacade::Null)
+                        | QueryArg::Value(JsValueFacade::Undefined)
+                )
+            });
+            if all_i32 {
+                let val = array_elements(items, |item| match item {
+                    QueryArg::Value(JsValueFacade::I32 { val }) => Some(val),
+                    _ => None,
+                })?;
+                Ok(qry_obj.bind(val))
+            } else {
+                let val = array_elements(items, |item| match item {
+                    QueryArg::Value(JsValueFacade::I32 { val }) => Some(val as f64),
+                    QueryArg::Value(JsValueFacade::F64 { val }) => Some(val),
+                    _ => None,
+                })?;
+                Ok(qry_obj.bind(val))
+            }
+        }
+        Some(QueryArg::Value(JsValueFacade::String { .. })) => {
+            let val = array_elements(items, |item| match item {
+                QueryArg::Value(JsValueFacade::String { val }) => Some(val.to_string()),
+                _ => None,
+            })?;
+            Ok(qry_obj.bind(val))
+        }
+        Some(QueryArg::Value(JsValueFacade::Boolean { .. })) => {
+            let val = array_elements(items, |item| match item {
+                QueryArg::Value(JsValueFacade::Boolean { val }) => Some(val),
+                _ => None,
+            })?;
+            Ok(qry_obj.bind(val))
+        }
+        Some(QueryArg::Value(JsValueFacade::TypedArray { .. })) => {
+            let val = array_elements(items, |item| match item {
+                QueryArg::Value(JsValueFacade::TypedArray { buffer, .. }) => Some(buffer),
+                _ => None,
+            })?;
+            Ok(qry_obj.bind(val))
+        }
+        Some(QueryArg::Value(JsValueFacade::JsObject { .. })) => {
+            let mut val: Vec<Option<serde_json::Value>> = vec![];
+            for item in array_elements(items, |item| match item {
+                QueryArg::Value(JsValueFacade::JsObject { cached_object }) => Some(cached_object),
+                _ => None,
+            })? {
+                val.push(match item {
+                    None => None,
+                    Some(cached_object) => {
+                        let json = cached_object.to_json_string().await?;
+                        Some(
+                            serde_json::from_str(json.as_str())
+                                .map_err(|e| JsError::new_string(format!("{e}")))?,
+                        )
+                    }
+                });
+            }
+            Ok(qry_obj.bind(val))
+        }
+        Some(_) => Err(JsError::new_str(
+            "unsupported element type in Array argument",
+        )),
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use crate::modules::db::sqlx::pgtypes::decode_numeric;
+
+    /// the binary representation of a NUMERIC
+    fn numeric(weight: i16, sign: u16, dscale: u16, digits: &[u16]) -> Vec<u8> {
+        let mut buf = vec![];
+        buf.extend_from_slice(&(digits.len() as u16).to_be_bytes());
+        buf.extend_from_slice(&weight.to_be_bytes());
+        buf.extend_from_slice(&sign.to_be_bytes());
+        buf.extend_from_slice(&dscale.to_be_bytes());
+        for digit in digits {
+            buf.extend_from_slice(&digit.to_be_bytes());
+        }
+        buf
+    }
+
+    #[test]
+    fn test_decode_numeric() {
+        let decode = |buf: Vec<u8>| decode_numeric(buf.as_slice()).expect("decode failed");
+
+        assert_eq!(decode(numeric(0, 0xC000, 0, &[])), "NaN");
+        assert_eq!(decode(numeric(0, 0xD000, 0, &[])), "Infinity");
+        assert_eq!(decode(numeric(0, 0xF000, 0, &[])), "-Infinity");
+        assert_eq!(decode(numeric(0, 0, 0, &[])), "0");
+        assert_eq!(decode(numeric(0, 0, 2, &[])), "0.00");
+        assert_eq!(decode(numeric(0, 0, 2, &[12, 5000])), "12.50");
+        assert_eq!(decode(numeric(-1, 0x4000, 4, &[12])), "-0.0012");
+        assert_eq!(decode(numeric(-2, 0, 8, &[12])), "0.00000012");
+        assert_eq!(decode(numeric(1, 0, 0, &[1])), "10000");
+        // 40 significant digits, more than rust_decimal can hold
+        assert_eq!(
+            decode(numeric(
+                9,
+                0,
+                0,
+                &[1234, 5678, 9012, 3456, 7890, 1234, 5678, 9012, 3456, 7890]
+            )),
+            "1234567890123456789012345678901234567890"
+        );
+        assert_eq!(
+            decode(numeric(
+                4,
+                0x4000,
+                20,
+                &[9, 9999, 9999, 9999, 9999, 9999, 9999, 9999, 9999, 9999]
+            )),
+            "-99999999999999999.99999999999999999999"
+        );
+        assert!(decode_numeric(&[0, 1]).is_err());
+    }
+}
@@ -0,0 +1,414 @@
+//! QueryStream proxy
+//!
+//! a QueryStream is obtained by calling stream() on a Connection or Transaction, it is an async
+//! iterator of rows
+//!
+//! rows are fetched one at a time, the next row is not read from the database until the script
+//! asked for it so huge result sets can be processed without buffering them in memory
+//!
+//! # Example
+//!
+//! ```javascript
+//! async function test() {
+//!     let sqlxMod = await import('greco://sqlx');
+//!     let con = await sqlxMod.connectPostgres('db.local', 5432, 'user', 'pass', 'mydb');
+//!     for await (let row of con.stream('SELECT id, name FROM users WHERE active = :active', {active: true})) {
+//!         console.log("user %s = %s", row[0], row[1]);
+//!     }
+//!     // rows as objects
+//!     for await (let user of con.stream('SELECT id, name FROM users', null, {rowMode: 'object'})) {
+//!         console.log("user %s = %s", user.id, user.name);
+//!     }
+//! }
+//! ```
+//!
+//! a stream on a Transaction holds the Transaction until it is done, other queries on that
+//! Transaction wait for it, so either consume the stream completely or break out of the loop
+//!
+//! the timeoutMs and signal options (see [cancel](../cancel/index.html)) apply to the stream as a
+//! whole, when the timeout expires or the signal is aborted next() rejects with a TimeoutError or
+//! an AbortError and the connection is released
+//!
+//! # Methods
+//!
+//! ##next(): Promise<{done: boolean, value: Array<any> | Record<string, any>}>
+//! ##return(): Promise<{done: true}>
+//! stop the stream, called automatically when breaking out of a for await loop
+//!
+
+use crate::modules::db::sqlx::mock::stream_query_mock;
+use crate::modules::db::sqlx::{
+    bind_mysql_args, bind_pg_args, bind_sqlite_args, describe_columns, invoke_js_function,
+    mysql_row_values, pg_row_values, prep_query_and_args, row_facade, sqlite_row_values, Protocol,
+    QueryArg, QueryOptions, SqlxConnection, SqlxTransaction,
+};
+use futures::TryStreamExt;
+use hirofa_utils::auto_id_map::AutoIdMap;
+use quickjs_runtime::jsutils::helper_tasks::add_helper_task_async;
+use quickjs_runtime::jsutils::jsproxies::JsProxy;
+use quickjs_runtime::jsutils::JsError;
+use quickjs_runtime::quickjsrealmadapter::QuickJsRealmAdapter;
+use quickjs_runtime::quickjsvalueadapter::QuickJsValueAdapter;
+use quickjs_runtime::values::JsValueFacade;
+use sqlx_lib::{MySqlConnection, PgConnection, SqliteConnection};
+use std::cell::RefCell;
+use std::sync::Arc;
+use tokio::sync::mpsc::{channel, Receiver, Sender};
+
+/// the number of rows which may be fetched before the script consumed them
+const STREAM_BUFFER_SIZE: usize = 1;
+
+pub(crate) type RowResult = Result<JsValueFacade, JsError>;
+type StreamReceiver = tokio::sync::Mutex<Receiver<RowResult>>;
+
+thread_local! {
+    static STREAMS: RefCell<AutoIdMap<Arc<StreamReceiver>>> = RefCell::new(AutoIdMap::new());
+}
+
+pub(crate) enum StreamSource {
+    Connection(Arc<SqlxConnection>),
+    Transaction(Arc<SqlxTransaction>),
+}
+
+impl StreamSource {
+    fn protocol(&self) -> Protocol {
+        match self {
+            StreamSource::Connection(con) => match &**con {
+                SqlxConnection::PostgresConnection { .. } => Protocol::Postgres,
+                SqlxConnection::MySqlConnection { .. } => Protocol::MySql,
+                SqlxConnection::SqliteConnection { .. } => Protocol::Sqlite,
+                SqlxConnection::MockConnection { mock, .. } => mock.protocol(),
+            },
+            StreamSource::Transaction(tx) => match &**tx {
+                SqlxTransaction::PostgresTransaction { .. } => Protocol::Postgres,
+                SqlxTransaction::MySqlTransaction { .. } => Protocol::MySql,
+                SqlxTransaction::SqliteTransaction { .. } => Protocol::Sqlite,
+                SqlxTransaction::MockTransaction { mock, .. } => mock.protocol(),
+            },
+        }
+    }
+}
+
+async fn stream_query_mysql(
+    qry: String,
+    args: Vec<QueryArg>,
+    executor: &mut MySqlConnection,
+    options: QueryOptions,
+    sender: &Sender<RowResult>,
+) -> Result<(), JsError> {
+    let qry_obj = bind_mysql_args(sqlx_lib::query(qry.as_str()), args).await?;
+    if let Some(on_columns) = &options.on_columns {
+        let columns = describe_columns(&mut *executor, qry.as_str()).await?;
+        invoke_js_function(on_columns, vec![columns]).await?;
+    }
+    let mut rows = qry_obj.fetch(executor);
+    while let Some(row) = rows
+        .try_next()
+        .await
+        .map_err(|e| JsError::new_string(format!("{e:?}")))?
+    {
+        // send waits until the script took the previous row
+        if sender
+            .send(Ok(row_facade(
+                options.row_mode,
+                &row,
+                mysql_row_values(&row)?,
+            )))
+            .await
+            .is_err()
+        {
+            // the QueryStream was returned or garbage collected
+            break;
+        }
+    }
+    Ok(())
+}
+
+async fn stream_query_postgres(
+    qry: String,
+    args: Vec<QueryArg>,
+    executor: &mut PgConnection,
+    options: QueryOptions,
+    sender: &Sender<RowResult>,
+) -> Result<(), JsError> {
+    let qry_obj = bind_pg_args(sqlx_lib::query(qry.as_str()), args).await?;
+    if let Some(on_columns) = &options.on_columns {
+        let columns = describe_columns(&mut *executor, qry.as_str()).await?;
+        invoke_js_function(on_columns, vec![columns]).await?;
+    }
+    let mut rows = qry_obj.fetch(executor);
+    while let Some(row) = rows
+        .try_next()
+        .await
+        .map_err(|e| JsError::new_string(format!("{e:?}")))?
+    {
+        if sender
+            .send(Ok(row_facade(options.row_mode, &row, pg_row_values(&row)?)))
+            .await
+            .is_err()
+        {
+            break;
+        }
+    }
+    Ok(())
+}
+
+async fn stream_query_sqlite(
+    qry: String,
+    args: Vec<QueryArg>,
+    executor: &mut SqliteConnection,
+    options: QueryOptions,
+    sender: &Sender<RowResult>,
+) -> Result<(), JsError> {
+    let qry_obj = bind_sqlite_args(sqlx_lib::query(qry.as_str()), args).await?;
+    if let Some(on_columns) = &options.on_columns {
+        let columns = describe_columns(&mut *executor, qry.as_str()).await?;
+        invoke_js_function(on_columns, vec![columns]).await?;
+    }
+    let mut rows = qry_obj.fetch(executor);
+    while let Some(row) = rows
+        .try_next()
+        .await
+        .map_err(|e| JsError::new_string(format!("{e:?}")))?
+    {
+        if sender
+            .send(Ok(row_facade(
+                options.row_mode,
+                &row,
+                sqlite_row_values(&row)?,
+            )))
+            .await
+            .is_err()
+        {
+            break;
+        }
+    }
+    Ok(())
+}
+
+async fn run_stream(
+    source: StreamSource,
+    qry: String,
+    args: Vec<QueryArg>,
+    mut options: QueryOptions,
+    sender: Sender<RowResult>,
+) {
+    // the timeout and signal apply to the stream as a whole, when the stream is cancelled the
+    // fetching is stopped and the connection is released
+    let cancellation = std::mem::take(&mut options.cancellation);
+    let fut = async {
+        match &source {
+            StreamSource::Connection(con) => match &**con {
+                SqlxConnection::PostgresConnection {
+                    pool: Some(pool), ..
+                } => match pool.acquire().await {
+                    Ok(mut con) => {
+                        stream_query_postgres(qry, args, &mut con, options, &sender).await
+                    }
+                    Err(e) => Err(JsError::new_string(format!("{e:?}"))),
+                },
+                SqlxConnection::MySqlConnection {
+                    pool: Some(pool), ..
+                } => match pool.acquire().await {
+                    Ok(mut con) => stream_query_mysql(qry, args, &mut con, options, &sender).await,
+                    Err(e) => Err(JsError::new_string(format!("{e:?}"))),
+                },
+                SqlxConnection::SqliteConnection {
+                    pool: Some(pool), ..
+                } => match pool.acquire().await {
+                    Ok(mut con) => stream_query_sqlite(qry, args, &mut con, options, &sender).await,
+                    Err(e) => Err(JsError::new_string(format!("{e:?}"))),
+                },
+                SqlxConnection::MockConnection { mock, .. } => {
+                    stream_query_mock(mock, qry, args, options, &sender).await
+                }
+                _ => Err(JsError::new_str("not connected")),
+            },
+            StreamSource::Transaction(tx) => match &**tx {
+                SqlxTransaction::PostgresTransaction { tx, .. } => {
+                    let write_locked = &mut *tx.lock().await;
+                    if let Some(tx) = write_locked {
+                        stream_query_postgres(qry, args, &mut **tx, options, &sender).await
+                    } else {
+                        Err(JsError::new_str("Transaction was closed"))
+                    }
+                }
+                SqlxTransaction::MySqlTransaction { tx, .. } => {
+                    let write_locked = &mut *tx.lock().await;
+                    if let Some(tx) = write_locked {
+                        stream_query_mysql(qry, args, &mut **tx, options, &sender).await
+                    } else {
+                        Err(JsError::new_str("Transaction was closed"))
+                    }
+                }
+                SqlxTransaction::SqliteTransaction { tx, .. } => {
+                    let write_locked = &mut *tx.lock().await;
+                    if let Some(tx) = write_locked {
+                        stream_query_sqlite(qry, args, &mut **tx, options, &sender).await
+                    } else {
+                        Err(JsError::new_str("Transaction was closed"))
+                    }
+                }
+                SqlxTransaction::MockTransaction { mock, open } => {
+                    let open = open.lock().await;
+                    if *open {
+                        stream_query_mock(mock, qry, args, options, &sender).await
+                    } else {
+                        Err(JsError::new_str("Transaction was closed"))
+                    }
+                }
+            },
+        }
+    };
+    let res = cancellation.run(fut).await.and_then(|res| res);
+    if let Err(e) = res {
+        // if the stream was returned already nobody is interested in the error
+        let _ = sender.send(Err(e)).await;
+    }
+}
+
+/// the stream() method of Connection and Transaction, starts fetching rows in a helper task and
+/// returns a QueryStream instance
+pub(crate) fn create_stream(
+    realm: &QuickJsRealmAdapter,
+    source: StreamSource,
+    args: &[QuickJsValueAdapter],
+) -> Result<QuickJsValueAdapter, JsError> {
+    if !(!args.is_empty()
+        && args.len() <= 3
+        && args[0].is_string()
+        && (args.len() == 1
+            || args[1].is_array()
+            || args[1].is_object()
+            || args[1].is_null_or_undefined()))
+    {
+        return Err(JsError::new_str("stream requires one to three args (qry: string, arguments?: null | Array<primitive> | Record<string, primitive>, options?: {rowMode?: 'array' | 'object', onColumns?: (columns) => void})"));
+    }
+    let options = QueryOptions::parse(realm, args.get(2))?;
+    let mut args = args.to_vec();
+    if args.len() == 1 || args[1].is_undefined() {
+        args.truncate(1);
+        args.push(realm.create_null()?);
+    }
+
+    let (qry, qry_args) = prep_query_and_args(realm, args, source.protocol())?;
+
+    let (sender, receiver) = stream_channel();
+    let _unused = add_helper_task_async(run_stream(source, qry, qry_args, options, sender));
+
+    instantiate_stream(realm, receiver)
+}
+
+/// the channel through which a helper task sends the values of a QueryStream
+pub(crate) fn stream_channel() -> (Sender<RowResult>, Receiver<RowResult>) {
+    channel(STREAM_BUFFER_SIZE)
+}
+
+/// create a QueryStream instance which yields the values received from a helper task
+pub(crate) fn instantiate_stream(
+    realm: &QuickJsRealmAdapter,
+    receiver: Receiver<RowResult>,
+) -> Result<QuickJsValueAdapter, JsError> {
+    let instance_id = STREAMS.with(|rc| {
+        let map = &mut *rc.borrow_mut();
+        map.insert(Arc::new(tokio::sync::Mutex::new(receiver)))
+    });
+    realm.instantiate_proxy_with_id(&["greco", "db", "sqlx"], "QueryStream", instance_id)
+}
+
+fn with_stream(instance_id: &usize) -> Result<Arc<StreamReceiver>, JsError> {
+    STREAMS.with(|rc| {
+        let map = &*rc.borrow();
+        map.get(instance_id)
+            .cloned()
+            .ok_or_else(|| JsError::new_str("no such QueryStream"))
+    })
+}
+
+/// resolve to an iterator result {done, value} when the next row was fetched
+fn stream_next(
+    realm: &QuickJsRealmAdapter,
+    receiver: Arc<StreamReceiver>,
+) -> Result<QuickJsValueAdapter, JsError> {
+    realm.create_resolving_promise_async(
+        async move {
+            let receiver = &mut *receiver.lock().await;
+            match receiver.recv().await {
+                Some(Ok(row)) => Ok(Some(row)),
+                Some(Err(e)) => Err(e),
+                None => Ok(None),
+            }
+        },
+        |realm, row_opt| {
+            let res = realm.create_object()?;
+            match row_opt {
+                None => {
+                    realm.set_object_property(&res, "done", &realm.create_boolean(true)?)?;
+                }
+                Some(row) => {
+                    realm.set_object_property(&res, "done", &realm.create_boolean(false)?)?;
+                    let value = realm.from_js_value_facade(row)?;
+                    realm.set_object_property(&res, "value", &value)?;
+                }
+            }
+            Ok(res)
+        },
+    )
+}
+
+/// stop fetching rows, rows which were fetched already may still be read with next()
+fn stream_return(
+    realm: &QuickJsRealmAdapter,
+    receiver: Arc<StreamReceiver>,
+) -> Result<QuickJsValueAdapter, JsError> {
+    realm.create_resolving_promise_async(
+        async move {
+            // closing the receiver makes the pending send in the helper task fail
+            receiver.lock().await.close();
+            Ok(())
+        },
+        |realm, _| {
+            let res = realm.create_object()?;
+            realm.set_object_property(&res, "done", &realm.create_boolean(true)?)?;
+            Ok(res)
+        },
+    )
+}
+
+pub(crate) fn create_query_stream_proxy(_realm: &QuickJsRealmAdapter) -> JsProxy {
+    JsProxy::new()
+        .namespace(&["greco", "db", "sqlx"])
+        .name("QueryStream")
+        .method("next", |_rt, realm, instance_id, _args| {
+            stream_next(realm, with_stream(instance_id)?)
+        })
+        .method("return", |_rt, realm, instance_id, _args| {
+            stream_return(realm, with_stream(instance_id)?)
+        })
+        .method("Symbol.asyncIterator", |_rt, realm, instance_id, _args| {
+            // return an object with next and return funcs which resolve to {done: false|true, value: null | nextVal}
+            let obj = realm.create_object()?;
+            let receiver = with_stream(instance_id)?;
+            let receiver2 = receiver.clone();
+            let next_func = realm.create_function(
+                "next",
+                move |realm, _this, _args| stream_next(realm, receiver.clone()),
+                0,
+            )?;
+            let return_func = realm.create_function(
+                "return",
+                move |realm, _this, _args| stream_return(realm, receiver2.clone()),
+                0,
+            )?;
+            realm.set_object_property(&obj, "next", &next_func)?;
+            realm.set_object_property(&obj, "return", &return_func)?;
+            Ok(obj)
+        })
+        .finalizer(|_rt, _realm, instance_id| {
+            // the helper task stops when the receiver is dropped
+            STREAMS.with(|rc| {
+                let map = &mut *rc.borrow_mut();
+                let _ = map.remove(&instance_id);
+            })
+        })
+}
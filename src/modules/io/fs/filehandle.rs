@@ -0,0 +1,210 @@
+//! FileHandle proxy
+//!
+//! a FileHandle is obtained by calling open() in the greco://fs module, it can be used to read or
+//! write files in chunks so they don't need to fit in memory
+//!
+//! # Example
+//!
+//! ```javascript
+//! async function test() {
+//!     let fs = await import('greco://fs');
+//!     let handle = await fs.open('./export.bin', 'w+');
+//!     try {
+//!         await handle.write(new Uint8Array([1, 2, 3, 4]));
+//!         await handle.seek(0);
+//!         let bytes = await handle.read(2); // Uint8Array [1, 2]
+//!     } finally {
+//!         await handle.close();
+//!     }
+//! }
+//! ```
+//!
+//! # Methods
+//!
+//! ##read(len: number): Promise<Uint8Array>
+//! reads at most len bytes, resolves to an empty Uint8Array when the end of the file was reached
+//! ##write(data: Uint8Array | string): Promise<number>
+//! ##seek(offset: number, whence?: 'start' | 'current' | 'end'): Promise<number>
+//! resolves to the new position in the file
+//! ##truncate(len?: number): Promise<void>
+//! ##sync(): Promise<void>
+//! ##close(): Promise<void>
+//!
+
+use crate::modules::io::fs::{data_arg, fs_promise, number_arg, FsError};
+use hirofa_utils::auto_id_map::AutoIdMap;
+use quickjs_runtime::jsutils::jsproxies::JsProxy;
+use quickjs_runtime::jsutils::JsError;
+use quickjs_runtime::quickjsrealmadapter::QuickJsRealmAdapter;
+use quickjs_runtime::quickjsvalueadapter::QuickJsValueAdapter;
+use std::cell::RefCell;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::sync::{Arc, Mutex};
+
+type FileHandle = Arc<Mutex<Option<File>>>;
+
+thread_local! {
+    static FILE_HANDLES: RefCell<AutoIdMap<FileHandle>> = RefCell::new(AutoIdMap::new());
+}
+
+pub(crate) fn store_file_handle(file: File) -> usize {
+    FILE_HANDLES.with(|rc| {
+        let map = &mut *rc.borrow_mut();
+        map.insert(Arc::new(Mutex::new(Some(file))))
+    })
+}
+
+fn with_file_handle(instance_id: &usize) -> Result<FileHandle, JsError> {
+    FILE_HANDLES.with(|rc| {
+        let map = &*rc.borrow();
+        map.get(instance_id)
+            .cloned()
+            .ok_or_else(|| JsError::new_str("no such FileHandle"))
+    })
+}
+
+/// run an operation on the File of a FileHandle in a helper thread
+fn with_file<C, R, M>(
+    realm: &QuickJsRealmAdapter,
+    instance_id: &usize,
+    syscall: &'static str,
+    consumer: C,
+    mapper: M,
+) -> Result<QuickJsValueAdapter, JsError>
+where
+    C: FnOnce(&mut File) -> std::io::Result<R> + Send + 'static,
+    R: Send + 'static,
+    M: FnOnce(&QuickJsRealmAdapter, R) -> Result<QuickJsValueAdapter, JsError> + Send + 'static,
+{
+    let handle = with_file_handle(instance_id)?;
+    fs_promise(
+        realm,
+        move || {
+            let locked = &mut *handle.lock().unwrap();
+            match locked {
+                Some(file) => consumer(file).map_err(|e| FsError::from_handle_io(e, syscall)),
+                None => Err(FsError::new("EBADF", "FileHandle was closed".to_string())),
+            }
+        },
+        mapper,
+    )
+}
+
+pub(crate) fn create_file_handle_proxy(_realm: &QuickJsRealmAdapter) -> JsProxy {
+    JsProxy::new()
+        .namespace(&["greco", "io", "fs"])
+        .name("FileHandle")
+        .method("read", |_rt, realm, instance_id, args| {
+            if args.len() != 1 || !(args[0].is_i32() || args[0].is_f64()) {
+                return Err(JsError::new_str("read requires one argument: (Number)"));
+            }
+            let len = number_arg(&args[0]) as u64;
+            with_file(
+                realm,
+                instance_id,
+                "read",
+                move |file| {
+                    let mut buffer = vec![];
+                    file.take(len).read_to_end(&mut buffer)?;
+                    Ok(buffer)
+                },
+                |realm, buffer| realm.create_typed_array_uint8(buffer),
+            )
+        })
+        .method("write", |_rt, realm, instance_id, args| {
+            let data = data_arg(
+                realm,
+                args,
+                0,
+                "write requires one argument: (Uint8Array | String)",
+            )?;
+            with_file(
+                realm,
+                instance_id,
+                "write",
+                move |file| {
+                    file.write_all(data.as_slice())?;
+                    Ok(data.len())
+                },
+                |realm, len| realm.create_f64(len as f64),
+            )
+        })
+        .method("seek", |_rt, realm, instance_id, args| {
+            if args.is_empty()
+                || !(args[0].is_i32() || args[0].is_f64())
+                || (args.len() > 1 && !args[1].is_string())
+            {
+                return Err(JsError::new_str(
+                    "seek requires one or two arguments: (Number, 'start' | 'current' | 'end')",
+                ));
+            }
+            let offset = number_arg(&args[0]) as i64;
+            let whence = if args.len() > 1 {
+                args[1].to_string()?
+            } else {
+                "start".to_string()
+            };
+            let pos = match whence.as_str() {
+                "start" if offset < 0 => {
+                    return Err(JsError::new_string(format!(
+                        "seek: offset from 'start' should not be negative, got {offset}"
+                    )))
+                }
+                "start" => SeekFrom::Start(offset as u64),
+                "current" => SeekFrom::Current(offset),
+                "end" => SeekFrom::End(offset),
+                _ => {
+                    return Err(JsError::new_string(format!(
+                        "seek: whence should be one of 'start', 'current' or 'end', got {whence}"
+                    )))
+                }
+            };
+            with_file(
+                realm,
+                instance_id,
+                "lseek",
+                move |file| file.seek(pos),
+                |realm, new_pos| realm.create_f64(new_pos as f64),
+            )
+        })
+        .method("truncate", |_rt, realm, instance_id, args| {
+            let len = match args.first() {
+                Some(arg) if arg.is_i32() || arg.is_f64() => number_arg(arg) as u64,
+                _ => 0,
+            };
+            with_file(
+                realm,
+                instance_id,
+                "ftruncate",
+                move |file| file.set_len(len),
+                |realm, _| realm.create_null(),
+            )
+        })
+        .method("sync", |_rt, realm, instance_id, _args| {
+            with_file(
+                realm,
+                instance_id,
+                "fsync",
+                |file| file.sync_all(),
+                |realm, _| realm.create_null(),
+            )
+        })
+        .method("close", |_rt, realm, instance_id, _args| {
+            let handle = with_file_handle(instance_id)?;
+            fs_promise(
+                realm,
+                move || {
+                    let _ = handle.lock().unwrap().take();
+                    Ok(())
+                },
+                |realm, _| realm.create_null(),
+            )
+        })
+        .finalizer(|_rt, _realm, instance_id| {
+            FILE_HANDLES.with(|rc| {
+                let map = &mut *rc.borrow_mut();
+                let _ = map.remove(&instance_id);
+            })
+        })
+}
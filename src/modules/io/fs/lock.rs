@@ -0,0 +1,69 @@
+//! FileLock proxy
+//!
+//! a FileLock is obtained by calling lock() in the greco://fs module, it is an advisory lock backed
+//! by flock which is released when the FileLock is closed or garbage collected
+//!
+//! # Example
+//!
+//! ```javascript
+//! async function test() {
+//!     let fs = await import('greco://fs');
+//!     // resolves when the lock was acquired
+//!     let lock = await fs.lock('./state.json.lock', {exclusive: true});
+//!     try {
+//!         let state = JSON.parse(await fs.readString('./state.json'));
+//!         state.counter++;
+//!         await fs.write('./state.json', JSON.stringify(state), {atomic: true});
+//!     } finally {
+//!         lock.close();
+//!     }
+//! }
+//! ```
+//!
+//! # Methods
+//!
+//! ##close(): void
+//!
+
+use hirofa_utils::auto_id_map::AutoIdMap;
+use quickjs_runtime::jsutils::jsproxies::JsProxy;
+use quickjs_runtime::quickjsrealmadapter::QuickJsRealmAdapter;
+use std::cell::RefCell;
+use std::fs::File;
+
+thread_local! {
+    static FILE_LOCKS: RefCell<AutoIdMap<File>> = RefCell::new(AutoIdMap::new());
+}
+
+pub(crate) fn store_file_lock(file: File) -> usize {
+    FILE_LOCKS.with(|rc| {
+        let map = &mut *rc.borrow_mut();
+        map.insert(file)
+    })
+}
+
+fn release_file_lock(instance_id: &usize) {
+    let file = FILE_LOCKS.with(|rc| {
+        let map = &mut *rc.borrow_mut();
+        map.remove_opt(instance_id)
+    });
+    if let Some(file) = file {
+        // closing the file also releases the lock, unlock explicitly anyway so a dup'ed fd does not keep it
+        if let Err(e) = file.unlock() {
+            log::error!("could not unlock file: {}", e);
+        }
+    }
+}
+
+pub(crate) fn create_file_lock_proxy(_realm: &QuickJsRealmAdapter) -> JsProxy {
+    JsProxy::new()
+        .namespace(&["greco", "io", "fs"])
+        .name("FileLock")
+        .method("close", |_rt, realm, instance_id, _args| {
+            release_file_lock(instance_id);
+            realm.create_null()
+        })
+        .finalizer(|_rt, _realm, instance_id| {
+            release_file_lock(&instance_id);
+        })
+}
@@ -57,97 +57,695 @@
 //!
 //! # Methods
 //!
-//! ##append
-//! ##copy
-//! ##createSymlink
-//! ##createDirs
-//! ##getMetadata
-//! ##getSymlinkMetadata
-//! ##list
-//! ##readString
-//! ##removeDir
-//! ##removeFile
-//! ##rename
-//! ##touch
-//! ##write
+//! All methods return a Promise and do their actual work in a helper thread, failures reject the
+//! Promise with an Error which has an errno style `code` property (e.g. `ENOENT`)
 //!
+//! ##append(path: string, data: string | Uint8Array): Promise<void>
+//! ##copy(from: string, to: string): Promise<void>
+//! ##createSymlink(target: string, path: string): Promise<void>
+//! ##createDirs(path: string): Promise<void>
+//! ##createTempDir(prefix?: string, dir?: string): Promise<TempPath>
+//! ##createTempFile(prefix?: string, dir?: string): Promise<TempPath>
+//! the TempPath is removed when it is garbage collected, see [tempfiles](tempfiles/index.html)
+//! ##getMetadata(path: string): Promise<Metadata>
+//! ##getSymlinkMetadata(path: string): Promise<Metadata>
+//! ##glob(pattern: string): Promise<Array<Entry>>
+//! see [walk](walk/index.html)
+//! ##list(path: string): Promise<Array<string>>
+//! ##lock(path: string, options?: {exclusive: boolean, wait: boolean, timeoutMs: number}): Promise<FileLock>
+//! see [lock](lock/index.html)
+//! ##open(path: string, flags?: 'r' | 'r+' | 'w' | 'w+' | 'a' | 'a+'): Promise<FileHandle>
+//! see [filehandle](filehandle/index.html)
+//! ##readBytes(path: string): Promise<Uint8Array>
+//! ##readString(path: string): Promise<string>
+//! ##removeDir(path: string, options?: {recursive: boolean}): Promise<void>
+//! ##removeFile(path: string): Promise<void>
+//! ##rename(from: string, to: string): Promise<void>
+//! ##touch(path: string): Promise<void>
+//! ##walk(root: string, options?: {maxDepth, followSymlinks, include, exclude}): FsWalker
+//! an async iterator of entries, see [walk](walk/index.html)
+//! ##watch(path: string, options?: {recursive: boolean}): FsWatcher
+//! see [watcher](watcher/index.html), unlike the other methods this does not return a Promise
+//! ##write(path: string, data: string | Uint8Array, options?: {atomic: boolean}): Promise<void>
+//! ##writeBytes(path: string, data: Uint8Array): Promise<void>
+//!
+//! # Sandboxing
+//!
+//! by default scripts may access any path the process has access to, use
+//! [FsModuleLoader::new](struct.FsModuleLoader.html#method.new) with allow_read and allow_write to
+//! restrict them to a set of root dirs, operations outside those roots reject with code `EACCES`
+//!
+//! the sandboxed loader should be added to the builder before calling init_greco_rt because that
+//! adds an unrestricted loader and the first loader which was added is used
+//!
+//! # Metadata
+//!
+//! getMetadata and getSymlinkMetadata resolve to an object like
+//!
+//! ```javascript
+//! {
+//!     size: 1234,
+//!     mtime: new Date(),
+//!     atime: new Date(),
+//!     ctime: new Date(),
+//!     isFile: true,
+//!     isDir: false,
+//!     isSymlink: false,
+//!     mode: 0o100644
+//! }
+//! ```
+//!
+//! # Errors
+//!
+//! operations reject with an Error with a `code` like `ENOENT` and the `path` it failed on, copy
+//! and rename errors also have the destination path as `dest`
+//!
+//! ```javascript
+//! async function test() {
+//!     let fs = await import('greco://fs');
+//!     try {
+//!         await fs.readString('./does_not_exist.txt');
+//!     } catch(ex) {
+//!         console.log(ex.code); // ENOENT
+//!     }
+//! }
+//! ```
+//!
+
+pub mod filehandle;
+pub mod lock;
+pub(crate) mod permissions;
+pub mod tempfiles;
+pub mod walk;
+pub mod watcher;
 
+use crate::modules::io::fs::permissions::{Access, FsPermissions};
 use quickjs_runtime::builder::QuickJsRuntimeBuilder;
 use quickjs_runtime::jsutils::modules::NativeModuleLoader;
-use quickjs_runtime::jsutils::JsError;
+use quickjs_runtime::jsutils::{JsError, Script};
+use quickjs_runtime::quickjs_utils::dates;
 use quickjs_runtime::quickjsrealmadapter::QuickJsRealmAdapter;
 use quickjs_runtime::quickjsvalueadapter::QuickJsValueAdapter;
-use quickjs_runtime::values::JsValueFacade;
 use std::fs;
+use std::future::Future;
+use std::io::ErrorKind;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-pub(crate) fn read_string(args: &[JsValueFacade]) -> Result<JsValueFacade, JsError> {
-    if args.len() != 1 || !args[0].is_string() {
-        Err(JsError::new_str(
-            "readString requires one argument: (String)",
-        ))
-    } else {
-        let path = args[0].get_str();
+/// an io error as it is reported to script, an Error with an errno style code
+pub(crate) struct FsError {
+    code: &'static str,
+    message: String,
+    path: Option<String>,
+    dest: Option<String>,
+}
+
+impl FsError {
+    pub(crate) fn new(code: &'static str, message: String) -> Self {
+        Self {
+            code,
+            message,
+            path: None,
+            dest: None,
+        }
+    }
+
+    pub(crate) fn from_handle_io(err: std::io::Error, syscall: &str) -> Self {
+        let code = errno_code(&err);
+        Self {
+            code,
+            message: format!("{code}: {err}, {syscall}"),
+            path: None,
+            dest: None,
+        }
+    }
+
+    pub(crate) fn from_io(err: std::io::Error, syscall: &str, path: &str) -> Self {
+        let code = errno_code(&err);
+        Self {
+            code,
+            message: format!("{code}: {err}, {syscall} '{path}'"),
+            path: Some(path.to_string()),
+            dest: None,
+        }
+    }
+
+    /// an error of an operation with a source and a destination like copy or rename, both paths
+    /// are reported because either of them may have caused it
+    pub(crate) fn from_io_dest(err: std::io::Error, syscall: &str, path: &str, dest: &str) -> Self {
+        let code = errno_code(&err);
+        Self {
+            code,
+            message: format!("{code}: {err}, {syscall} '{path}' -> '{dest}'"),
+            path: Some(path.to_string()),
+            dest: Some(dest.to_string()),
+        }
+    }
+
+    pub(crate) fn to_js_value(
+        &self,
+        realm: &QuickJsRealmAdapter,
+    ) -> Result<QuickJsValueAdapter, JsError> {
+        let err = realm.create_error("Error", self.message.as_str(), "")?;
+        realm.set_object_property(&err, "code", &realm.create_string(self.code)?)?;
+        if let Some(path) = &self.path {
+            realm.set_object_property(&err, "path", &realm.create_string(path.as_str())?)?;
+        }
+        if let Some(dest) = &self.dest {
+            realm.set_object_property(&err, "dest", &realm.create_string(dest.as_str())?)?;
+        }
+        Ok(err)
+    }
+}
+
+impl From<FsError> for JsError {
+    fn from(err: FsError) -> Self {
+        JsError::new_string(err.message)
+    }
+}
 
-        match fs::read_to_string(path) {
-            Ok(s) => Ok(JsValueFacade::new_string(s)),
-            Err(e) => Err(JsError::new_string(format!("{e:?}"))),
+fn errno_code(err: &std::io::Error) -> &'static str {
+    match err.kind() {
+        ErrorKind::NotFound => "ENOENT",
+        ErrorKind::PermissionDenied => "EACCES",
+        ErrorKind::AlreadyExists => "EEXIST",
+        ErrorKind::NotADirectory => "ENOTDIR",
+        ErrorKind::IsADirectory => "EISDIR",
+        ErrorKind::DirectoryNotEmpty => "ENOTEMPTY",
+        ErrorKind::InvalidInput => "EINVAL",
+        ErrorKind::ReadOnlyFilesystem => "EROFS",
+        ErrorKind::StorageFull => "ENOSPC",
+        ErrorKind::CrossesDevices => "EXDEV",
+        ErrorKind::TooManyLinks => "EMLINK",
+        ErrorKind::WouldBlock => "EAGAIN",
+        ErrorKind::Interrupted => "EINTR",
+        ErrorKind::TimedOut => "ETIMEDOUT",
+        ErrorKind::Unsupported => "ENOTSUP",
+        ErrorKind::UnexpectedEof => "EOF",
+        _ => "EIO",
+    }
+}
+
+/// run an fs operation in a helper thread and settle the returned Promise with its result
+pub(crate) fn fs_promise<P, R, M>(
+    realm: &QuickJsRealmAdapter,
+    producer: P,
+    mapper: M,
+) -> Result<QuickJsValueAdapter, JsError>
+where
+    P: FnOnce() -> Result<R, FsError> + Send + 'static,
+    R: Send + 'static,
+    M: FnOnce(&QuickJsRealmAdapter, R) -> Result<QuickJsValueAdapter, JsError> + Send + 'static,
+{
+    realm.create_resolving_promise(
+        move || Ok(producer()),
+        move |realm, res| match res {
+            Ok(val) => mapper(realm, val),
+            Err(fs_err) => {
+                // resolving with a rejected promise rejects the returned promise with our own Error obj
+                let rejected = realm.create_promise()?;
+                rejected.js_promise_reject(realm, &fs_err.to_js_value(realm)?)?;
+                Ok(rejected.js_promise_get_value(realm))
+            }
+        },
+    )
+}
+
+/// like fs_promise but for an operation which awaits, e.g. to retry with a backoff without
+/// occupying a helper thread
+pub(crate) fn fs_promise_async<F, R, M>(
+    realm: &QuickJsRealmAdapter,
+    producer: F,
+    mapper: M,
+) -> Result<QuickJsValueAdapter, JsError>
+where
+    F: Future<Output = Result<R, FsError>> + Send + 'static,
+    R: Send + 'static,
+    M: FnOnce(&QuickJsRealmAdapter, R) -> Result<QuickJsValueAdapter, JsError> + Send + 'static,
+{
+    realm.create_resolving_promise_async(async move { Ok(producer.await) }, move |realm, res| {
+        match res {
+            Ok(val) => mapper(realm, val),
+            Err(fs_err) => {
+                let rejected = realm.create_promise()?;
+                rejected.js_promise_reject(realm, &fs_err.to_js_value(realm)?)?;
+                Ok(rejected.js_promise_get_value(realm))
+            }
         }
+    })
+}
+
+fn string_arg(args: &[QuickJsValueAdapter], index: usize, usage: &str) -> Result<String, JsError> {
+    match args.get(index) {
+        Some(arg) if arg.is_string() => arg.to_string(),
+        _ => Err(JsError::new_string(usage.to_string())),
     }
 }
 
-pub(crate) fn remove_file(args: &[JsValueFacade]) -> Result<JsValueFacade, JsError> {
-    if args.len() != 1 || !args[0].is_string() {
-        Err(JsError::new_str(
-            "removeFile requires one argument: (String)",
-        ))
+/// get a number arg which may be passed as an i32 or an f64
+pub(crate) fn number_arg(arg: &QuickJsValueAdapter) -> f64 {
+    if arg.is_i32() {
+        arg.to_i32() as f64
     } else {
-        let path = args[0].get_str();
+        arg.to_f64()
+    }
+}
 
-        match fs::remove_file(path) {
-            Ok(_) => Ok(JsValueFacade::Null),
-            Err(e) => Err(JsError::new_string(format!("{e:?}"))),
+/// get the bytes of a Uint8Array or string arg, other types are rejected
+pub(crate) fn data_arg(
+    realm: &QuickJsRealmAdapter,
+    args: &[QuickJsValueAdapter],
+    index: usize,
+    usage: &str,
+) -> Result<Vec<u8>, JsError> {
+    match args.get(index) {
+        Some(arg) if arg.is_typed_array() => realm.copy_typed_array_buffer(arg),
+        Some(arg) if arg.is_string() => Ok(arg.to_string()?.into_bytes()),
+        _ => Err(JsError::new_string(usage.to_string())),
+    }
+}
+
+fn bool_option(
+    realm: &QuickJsRealmAdapter,
+    options: Option<&QuickJsValueAdapter>,
+    name: &str,
+) -> Result<bool, JsError> {
+    match options {
+        Some(options) if options.is_object() => {
+            let val = realm.get_object_property(options, name)?;
+            Ok(val.is_bool() && val.to_bool())
+        }
+        _ => Ok(false),
+    }
+}
+
+/// the metadata of a file or dir as it is passed to script
+pub(crate) struct FileStats {
+    size: u64,
+    mtime: Option<f64>,
+    atime: Option<f64>,
+    ctime: Option<f64>,
+    is_file: bool,
+    is_dir: bool,
+    is_symlink: bool,
+    mode: u32,
+}
+
+fn millis_since_epoch(time: std::io::Result<SystemTime>) -> Option<f64> {
+    time.ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_secs_f64() * 1000.0)
+}
+
+impl From<&fs::Metadata> for FileStats {
+    fn from(meta: &fs::Metadata) -> Self {
+        #[cfg(unix)]
+        let (ctime, mode) = {
+            use std::os::unix::fs::MetadataExt;
+            (
+                Some(meta.ctime() as f64 * 1000.0 + (meta.ctime_nsec() / 1_000_000) as f64),
+                meta.mode(),
+            )
+        };
+        #[cfg(not(unix))]
+        let (ctime, mode) = (
+            millis_since_epoch(meta.created()),
+            if meta.permissions().readonly() {
+                0o444
+            } else {
+                0o666
+            },
+        );
+
+        Self {
+            size: meta.len(),
+            mtime: millis_since_epoch(meta.modified()),
+            atime: millis_since_epoch(meta.accessed()),
+            ctime,
+            is_file: meta.is_file(),
+            is_dir: meta.is_dir(),
+            is_symlink: meta.file_type().is_symlink(),
+            mode,
+        }
+    }
+}
+
+impl FileStats {
+    pub(crate) fn to_js_value(
+        &self,
+        realm: &QuickJsRealmAdapter,
+    ) -> Result<QuickJsValueAdapter, JsError> {
+        let obj = realm.create_object()?;
+        realm.set_object_property(&obj, "size", &realm.create_f64(self.size as f64)?)?;
+        for (name, time) in [
+            ("mtime", self.mtime),
+            ("atime", self.atime),
+            ("ctime", self.ctime),
+        ] {
+            let val = match time {
+                Some(millis) => {
+                    let date = dates::new_date_q(realm)?;
+                    dates::set_time_q(realm, &date, millis)?;
+                    date
+                }
+                None => realm.create_null()?,
+            };
+            realm.set_object_property(&obj, name, &val)?;
         }
+        realm.set_object_property(&obj, "isFile", &realm.create_boolean(self.is_file)?)?;
+        realm.set_object_property(&obj, "isDir", &realm.create_boolean(self.is_dir)?)?;
+        realm.set_object_property(&obj, "isSymlink", &realm.create_boolean(self.is_symlink)?)?;
+        realm.set_object_property(&obj, "mode", &realm.create_i32(self.mode as i32)?)?;
+        Ok(obj)
     }
 }
 
-pub(crate) fn append(_args: &[JsValueFacade]) -> Result<JsValueFacade, JsError> {
-    unimplemented!()
+pub(crate) fn read_string(
+    permissions: &Arc<FsPermissions>,
+    realm: &QuickJsRealmAdapter,
+    _this: &QuickJsValueAdapter,
+    args: &[QuickJsValueAdapter],
+) -> Result<QuickJsValueAdapter, JsError> {
+    let path = string_arg(args, 0, "readString requires one argument: (String)")?;
+    let permissions = permissions.clone();
+    fs_promise(
+        realm,
+        move || {
+            let real = permissions.check(&path, Access::Read, true, "open")?;
+            fs::read_to_string(real).map_err(|e| FsError::from_io(e, "open", &path))
+        },
+        |realm, s| realm.create_string(s.as_str()),
+    )
 }
 
-pub(crate) fn copy(_args: &[JsValueFacade]) -> Result<JsValueFacade, JsError> {
-    unimplemented!()
+pub(crate) fn remove_file(
+    permissions: &Arc<FsPermissions>,
+    realm: &QuickJsRealmAdapter,
+    _this: &QuickJsValueAdapter,
+    args: &[QuickJsValueAdapter],
+) -> Result<QuickJsValueAdapter, JsError> {
+    let path = string_arg(args, 0, "removeFile requires one argument: (String)")?;
+    let permissions = permissions.clone();
+    fs_promise(
+        realm,
+        move || {
+            let real = permissions.check(&path, Access::Write, false, "unlink")?;
+            fs::remove_file(real).map_err(|e| FsError::from_io(e, "unlink", &path))
+        },
+        |realm, _| realm.create_null(),
+    )
 }
 
-pub(crate) fn create_symlink(_args: &[JsValueFacade]) -> Result<JsValueFacade, JsError> {
-    unimplemented!()
+/// append
+/// append to a file, the file is created if it does not exist
+/// # Example
+/// ```javascript
+/// async function append_example() {
+///    let fs = await import('greco://fs');
+///    await fs.append('./test.log', 'hello world\n');
+/// }
+/// ```
+pub(crate) fn append(
+    permissions: &Arc<FsPermissions>,
+    realm: &QuickJsRealmAdapter,
+    _this: &QuickJsValueAdapter,
+    args: &[QuickJsValueAdapter],
+) -> Result<QuickJsValueAdapter, JsError> {
+    let usage = "append requires two arguments: (String, String | Uint8Array)";
+    let path = string_arg(args, 0, usage)?;
+    let content = data_arg(realm, args, 1, usage)?;
+    let permissions = permissions.clone();
+    fs_promise(
+        realm,
+        move || {
+            use std::io::Write;
+            let real = permissions.check(&path, Access::Write, true, "open")?;
+            fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(real)
+                .and_then(|mut file| file.write_all(content.as_slice()))
+                .map_err(|e| FsError::from_io(e, "open", &path))
+        },
+        |realm, _| realm.create_null(),
+    )
 }
 
-pub(crate) fn create_dirs(_args: &[JsValueFacade]) -> Result<JsValueFacade, JsError> {
-    unimplemented!()
+pub(crate) fn copy(
+    permissions: &Arc<FsPermissions>,
+    realm: &QuickJsRealmAdapter,
+    _this: &QuickJsValueAdapter,
+    args: &[QuickJsValueAdapter],
+) -> Result<QuickJsValueAdapter, JsError> {
+    let usage = "copy requires two arguments: (String, String)";
+    let from = string_arg(args, 0, usage)?;
+    let to = string_arg(args, 1, usage)?;
+    let permissions = permissions.clone();
+    fs_promise(
+        realm,
+        move || {
+            let real_from = permissions.check(&from, Access::Read, true, "copyfile")?;
+            let real_to = permissions.check(&to, Access::Write, true, "copyfile")?;
+            fs::copy(real_from, real_to)
+                .map(|_| ())
+                .map_err(|e| FsError::from_io_dest(e, "copyfile", &from, &to))
+        },
+        |realm, _| realm.create_null(),
+    )
 }
 
-pub(crate) fn get_metadata(_args: &[JsValueFacade]) -> Result<JsValueFacade, JsError> {
-    unimplemented!()
+pub(crate) fn create_symlink(
+    permissions: &Arc<FsPermissions>,
+    realm: &QuickJsRealmAdapter,
+    _this: &QuickJsValueAdapter,
+    args: &[QuickJsValueAdapter],
+) -> Result<QuickJsValueAdapter, JsError> {
+    let usage = "createSymlink requires two arguments: (target: String, path: String)";
+    let target = string_arg(args, 0, usage)?;
+    let path = string_arg(args, 1, usage)?;
+    let permissions = permissions.clone();
+    fs_promise(
+        realm,
+        move || {
+            // the target is not checked, following a link to outside of the roots is refused later on
+            let real = permissions.check(&path, Access::Write, false, "symlink")?;
+            #[cfg(unix)]
+            let res = std::os::unix::fs::symlink(&target, real);
+            #[cfg(windows)]
+            let res = std::os::windows::fs::symlink_file(&target, real);
+            res.map_err(|e| FsError::from_io(e, "symlink", &path))
+        },
+        |realm, _| realm.create_null(),
+    )
 }
 
-pub(crate) fn get_symlink_metadata(_args: &[JsValueFacade]) -> Result<JsValueFacade, JsError> {
-    unimplemented!()
+pub(crate) fn create_dirs(
+    permissions: &Arc<FsPermissions>,
+    realm: &QuickJsRealmAdapter,
+    _this: &QuickJsValueAdapter,
+    args: &[QuickJsValueAdapter],
+) -> Result<QuickJsValueAdapter, JsError> {
+    let path = string_arg(args, 0, "createDirs requires one argument: (String)")?;
+    let permissions = permissions.clone();
+    fs_promise(
+        realm,
+        move || {
+            let real = permissions.check(&path, Access::Write, true, "mkdir")?;
+            fs::create_dir_all(real).map_err(|e| FsError::from_io(e, "mkdir", &path))
+        },
+        |realm, _| realm.create_null(),
+    )
 }
 
-pub(crate) fn list(_args: &[JsValueFacade]) -> Result<JsValueFacade, JsError> {
-    unimplemented!()
+pub(crate) fn get_metadata(
+    permissions: &Arc<FsPermissions>,
+    realm: &QuickJsRealmAdapter,
+    _this: &QuickJsValueAdapter,
+    args: &[QuickJsValueAdapter],
+) -> Result<QuickJsValueAdapter, JsError> {
+    let path = string_arg(args, 0, "getMetadata requires one argument: (String)")?;
+    let permissions = permissions.clone();
+    fs_promise(
+        realm,
+        move || {
+            let real = permissions.check(&path, Access::Read, true, "stat")?;
+            fs::metadata(real)
+                .map(|meta| FileStats::from(&meta))
+                .map_err(|e| FsError::from_io(e, "stat", &path))
+        },
+        |realm, stats| stats.to_js_value(realm),
+    )
 }
 
-pub(crate) fn remove_dir(_args: &[JsValueFacade]) -> Result<JsValueFacade, JsError> {
-    unimplemented!()
+pub(crate) fn get_symlink_metadata(
+    permissions: &Arc<FsPermissions>,
+    realm: &QuickJsRealmAdapter,
+    _this: &QuickJsValueAdapter,
+    args: &[QuickJsValueAdapter],
+) -> Result<QuickJsValueAdapter, JsError> {
+    let path = string_arg(
+        args,
+        0,
+        "getSymlinkMetadata requires one argument: (String)",
+    )?;
+    let permissions = permissions.clone();
+    fs_promise(
+        realm,
+        move || {
+            let real = permissions.check(&path, Access::Read, false, "lstat")?;
+            fs::symlink_metadata(real)
+                .map(|meta| FileStats::from(&meta))
+                .map_err(|e| FsError::from_io(e, "lstat", &path))
+        },
+        |realm, stats| stats.to_js_value(realm),
+    )
 }
 
-pub(crate) fn rename(_args: &[JsValueFacade]) -> Result<JsValueFacade, JsError> {
-    unimplemented!()
+/// list
+/// list the names of the entries in a directory
+/// # Example
+/// ```javascript
+/// async function list_example() {
+///    let fs = await import('greco://fs');
+///    for (let name of await fs.list('./')) {
+///        console.log(name);
+///    }
+/// }
+/// ```
+pub(crate) fn list(
+    permissions: &Arc<FsPermissions>,
+    realm: &QuickJsRealmAdapter,
+    _this: &QuickJsValueAdapter,
+    args: &[QuickJsValueAdapter],
+) -> Result<QuickJsValueAdapter, JsError> {
+    let path = string_arg(args, 0, "list requires one argument: (String)")?;
+    let permissions = permissions.clone();
+    fs_promise(
+        realm,
+        move || {
+            let mut names = vec![];
+            let real = permissions.check(&path, Access::Read, true, "scandir")?;
+            let read_dir = fs::read_dir(real).map_err(|e| FsError::from_io(e, "scandir", &path))?;
+            for entry in read_dir {
+                let entry = entry.map_err(|e| FsError::from_io(e, "scandir", &path))?;
+                names.push(entry.file_name().to_string_lossy().to_string());
+            }
+            names.sort();
+            Ok(names)
+        },
+        |realm, names| {
+            let arr = realm.create_array()?;
+            for name in names {
+                realm.push_array_element(&arr, &realm.create_string(name.as_str())?)?;
+            }
+            Ok(arr)
+        },
+    )
+}
+
+pub(crate) fn remove_dir(
+    permissions: &Arc<FsPermissions>,
+    realm: &QuickJsRealmAdapter,
+    _this: &QuickJsValueAdapter,
+    args: &[QuickJsValueAdapter],
+) -> Result<QuickJsValueAdapter, JsError> {
+    let path = string_arg(
+        args,
+        0,
+        "removeDir requires one or two arguments: (String, {recursive: boolean}?)",
+    )?;
+    let recursive = bool_option(realm, args.get(1), "recursive")?;
+    let permissions = permissions.clone();
+    fs_promise(
+        realm,
+        move || {
+            let real = permissions.check(&path, Access::Write, false, "rmdir")?;
+            if recursive {
+                fs::remove_dir_all(real)
+            } else {
+                fs::remove_dir(real)
+            }
+            .map_err(|e| FsError::from_io(e, "rmdir", &path))
+        },
+        |realm, _| realm.create_null(),
+    )
+}
+
+pub(crate) fn rename(
+    permissions: &Arc<FsPermissions>,
+    realm: &QuickJsRealmAdapter,
+    _this: &QuickJsValueAdapter,
+    args: &[QuickJsValueAdapter],
+) -> Result<QuickJsValueAdapter, JsError> {
+    let usage = "rename requires two arguments: (String, String)";
+    let from = string_arg(args, 0, usage)?;
+    let to = string_arg(args, 1, usage)?;
+    let permissions = permissions.clone();
+    fs_promise(
+        realm,
+        move || {
+            let real_from = permissions.check(&from, Access::Write, false, "rename")?;
+            let real_to = permissions.check(&to, Access::Write, false, "rename")?;
+            fs::rename(real_from, real_to)
+                .map_err(|e| FsError::from_io_dest(e, "rename", &from, &to))
+        },
+        |realm, _| realm.create_null(),
+    )
+}
+
+/// touch
+/// create an empty file if it does not exist or update the modified time if it does
+pub(crate) fn touch(
+    permissions: &Arc<FsPermissions>,
+    realm: &QuickJsRealmAdapter,
+    _this: &QuickJsValueAdapter,
+    args: &[QuickJsValueAdapter],
+) -> Result<QuickJsValueAdapter, JsError> {
+    let path = string_arg(args, 0, "touch requires one argument: (String)")?;
+    let permissions = permissions.clone();
+    fs_promise(
+        realm,
+        move || {
+            let real = permissions.check(&path, Access::Write, true, "utimes")?;
+            fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(real)
+                .and_then(|file| file.set_modified(SystemTime::now()))
+                .map_err(|e| FsError::from_io(e, "utimes", &path))
+        },
+        |realm, _| realm.create_null(),
+    )
 }
 
-pub(crate) fn touch(_args: &[JsValueFacade]) -> Result<JsValueFacade, JsError> {
-    unimplemented!()
+/// write data to a sibling temp file, fsync it and rename it to path so readers never see a
+/// partially written file
+fn write_atomic(path: &Path, content: &[u8]) -> std::io::Result<()> {
+    use std::io::Write;
+    let dir = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let name = path
+        .file_name()
+        .ok_or_else(|| std::io::Error::new(ErrorKind::InvalidInput, "not a file path"))?;
+    let prefix = format!(".{}.", name.to_string_lossy());
+    // the temp file is removed when we fail before it was renamed, a new file gets the same
+    // default mode as one created by a plain write
+    let temp_path = tempfiles::TempPath::create_with_mode(dir, prefix.as_str(), false, 0o666)?;
+    let mut file = fs::OpenOptions::new().write(true).open(temp_path.path())?;
+    file.write_all(content)?;
+    if let Ok(meta) = fs::metadata(path) {
+        file.set_permissions(meta.permissions())?;
+    }
+    file.sync_all()?;
+    drop(file);
+    temp_path.persist(path)?;
+    // make sure the rename itself is durable
+    #[cfg(unix)]
+    fs::File::open(dir)?.sync_all()?;
+    Ok(())
 }
 
 /// write
@@ -157,29 +755,552 @@ pub(crate) fn touch(_args: &[JsValueFacade]) -> Result<JsValueFacade, JsError> {
 /// async function write_example() {
 ///    let fs = await import('greco://fs');
 ///    await fs.write('./test.txt', 'hello world');
+///    // write to a temp file and rename it into place
+///    await fs.write('./state.json', '{}', {atomic: true});
 /// }
 /// ```
-pub(crate) fn write(args: &[JsValueFacade]) -> Result<JsValueFacade, JsError> {
-    if args.len() != 2 || !args[0].is_string() {
-        Err(JsError::new_str(
-            "write requires two arguments: (String, obj)",
-        ))
-    } else {
-        let path = args[0].get_str();
-        let content = if args[1].is_string() {
-            args[1].get_str().to_string()
+pub(crate) fn write(
+    permissions: &Arc<FsPermissions>,
+    realm: &QuickJsRealmAdapter,
+    _this: &QuickJsValueAdapter,
+    args: &[QuickJsValueAdapter],
+) -> Result<QuickJsValueAdapter, JsError> {
+    let usage =
+        "write requires two or three arguments: (String, String | Uint8Array, {atomic: boolean}?)";
+    if args.len() < 2 || args.len() > 3 {
+        return Err(JsError::new_str(usage));
+    }
+    let path = string_arg(args, 0, usage)?;
+    let content = data_arg(realm, args, 1, usage)?;
+    let atomic = bool_option(realm, args.get(2), "atomic")?;
+
+    let permissions = permissions.clone();
+    fs_promise(
+        realm,
+        move || {
+            let real = permissions.check(&path, Access::Write, true, "open")?;
+            if atomic {
+                write_atomic(real.as_path(), content.as_slice())
+                    .map_err(|e| FsError::from_io(e, "rename", &path))
+            } else {
+                fs::write(real, content).map_err(|e| FsError::from_io(e, "open", &path))
+            }
+        },
+        |realm, _| realm.create_null(),
+    )
+}
+
+/// lock
+/// acquire an advisory lock on a file (which is created if it does not exist), resolves to a
+/// FileLock when the lock was acquired
+///
+/// a contended lock is retried with a backoff, with wait: false the promise rejects with EAGAIN
+/// right away and with timeoutMs it rejects with ETIMEDOUT when the lock was not acquired in time
+/// # Example
+/// ```javascript
+/// async function lock_example() {
+///    let fs = await import('greco://fs');
+///    let lock = await fs.lock('./state.lock', {exclusive: false, timeoutMs: 5000});
+///    // read state here
+///    lock.close();
+/// }
+/// ```
+pub(crate) fn lock(
+    permissions: &Arc<FsPermissions>,
+    realm: &QuickJsRealmAdapter,
+    _this: &QuickJsValueAdapter,
+    args: &[QuickJsValueAdapter],
+) -> Result<QuickJsValueAdapter, JsError> {
+    let path = string_arg(
+        args,
+        0,
+        "lock requires one or two arguments: (String, {exclusive: boolean, wait: boolean, timeoutMs: number}?)",
+    )?;
+    // locks are exclusive unless explicitly asked otherwise and wait unless asked otherwise
+    let mut exclusive = true;
+    let mut wait = true;
+    let mut timeout = None;
+    if let Some(options) = args.get(1).filter(|o| o.is_object()) {
+        let val = realm.get_object_property(options, "exclusive")?;
+        exclusive = !val.is_bool() || val.to_bool();
+        let val = realm.get_object_property(options, "wait")?;
+        wait = !val.is_bool() || val.to_bool();
+        let val = realm.get_object_property(options, "timeoutMs")?;
+        if val.is_i32() || val.is_f64() {
+            let millis = number_arg(&val);
+            if !millis.is_finite() || millis < 0.0 {
+                return Err(JsError::new_str(
+                    "lock: timeoutMs should be a positive number",
+                ));
+            }
+            timeout = Some(Duration::from_millis(millis as u64));
+        } else if !val.is_null_or_undefined() {
+            return Err(JsError::new_str("lock: timeoutMs should be a number"));
+        }
+    }
+    let permissions = permissions.clone();
+    fs_promise_async(
+        realm,
+        async move {
+            let open_path = path.clone();
+            let file = tokio::task::spawn_blocking(move || {
+                let real = permissions.check(&open_path, Access::Write, true, "flock")?;
+                fs::OpenOptions::new()
+                    .read(true)
+                    .write(true)
+                    .create(true)
+                    .truncate(false)
+                    .open(real)
+                    .map_err(|e| FsError::from_io(e, "open", &open_path))
+            })
+            .await
+            .map_err(|e| FsError::new("EIO", format!("EIO: {e}, open '{path}'")))??;
+
+            let start = Instant::now();
+            let mut backoff = Duration::from_millis(5);
+            loop {
+                let res = if exclusive {
+                    file.try_lock()
+                } else {
+                    file.try_lock_shared()
+                };
+                match res {
+                    Ok(()) => return Ok(file),
+                    Err(fs::TryLockError::WouldBlock) => {}
+                    Err(fs::TryLockError::Error(e)) => {
+                        return Err(FsError::from_io(e, "flock", &path))
+                    }
+                }
+                if !wait {
+                    return Err(FsError::new(
+                        "EAGAIN",
+                        format!("EAGAIN: file is locked, flock '{path}'"),
+                    ));
+                }
+                let mut sleep = backoff;
+                if let Some(timeout) = timeout {
+                    let elapsed = start.elapsed();
+                    if elapsed >= timeout {
+                        return Err(FsError::new(
+                            "ETIMEDOUT",
+                            format!("ETIMEDOUT: lock was not acquired in time, flock '{path}'"),
+                        ));
+                    }
+                    sleep = sleep.min(timeout - elapsed);
+                }
+                tokio::time::sleep(sleep).await;
+                backoff = (backoff * 2).min(Duration::from_millis(250));
+            }
+        },
+        |realm, file| {
+            let id = lock::store_file_lock(file);
+            realm.instantiate_proxy_with_id(&["greco", "io", "fs"], "FileLock", id)
+        },
+    )
+}
+
+/// readBytes
+/// read the contents of a file as a Uint8Array
+pub(crate) fn read_bytes(
+    permissions: &Arc<FsPermissions>,
+    realm: &QuickJsRealmAdapter,
+    _this: &QuickJsValueAdapter,
+    args: &[QuickJsValueAdapter],
+) -> Result<QuickJsValueAdapter, JsError> {
+    let path = string_arg(args, 0, "readBytes requires one argument: (String)")?;
+    let permissions = permissions.clone();
+    fs_promise(
+        realm,
+        move || {
+            let real = permissions.check(&path, Access::Read, true, "open")?;
+            fs::read(real).map_err(|e| FsError::from_io(e, "open", &path))
+        },
+        |realm, bytes| realm.create_typed_array_uint8(bytes),
+    )
+}
+
+/// writeBytes
+/// write a Uint8Array to a file, the file is created or truncated
+/// # Example
+/// ```javascript
+/// async function write_bytes_example() {
+///    let fs = await import('greco://fs');
+///    await fs.writeBytes('./test.bin', new Uint8Array([1, 2, 3]));
+/// }
+/// ```
+pub(crate) fn write_bytes(
+    permissions: &Arc<FsPermissions>,
+    realm: &QuickJsRealmAdapter,
+    _this: &QuickJsValueAdapter,
+    args: &[QuickJsValueAdapter],
+) -> Result<QuickJsValueAdapter, JsError> {
+    let usage = "writeBytes requires two arguments: (String, Uint8Array)";
+    let path = string_arg(args, 0, usage)?;
+    let content = match args.get(1) {
+        Some(arg) if arg.is_typed_array() => realm.copy_typed_array_buffer(arg)?,
+        _ => return Err(JsError::new_str(usage)),
+    };
+    let permissions = permissions.clone();
+    fs_promise(
+        realm,
+        move || {
+            let real = permissions.check(&path, Access::Write, true, "open")?;
+            fs::write(real, content).map_err(|e| FsError::from_io(e, "open", &path))
+        },
+        |realm, _| realm.create_null(),
+    )
+}
+
+/// create a temp file or dir for createTempFile and createTempDir
+fn create_temp_path(
+    permissions: &Arc<FsPermissions>,
+    realm: &QuickJsRealmAdapter,
+    args: &[QuickJsValueAdapter],
+    is_dir: bool,
+    usage: &str,
+) -> Result<QuickJsValueAdapter, JsError> {
+    let prefix = match args.first() {
+        Some(arg) if arg.is_string() => arg.to_string()?,
+        Some(arg) if !arg.is_null_or_undefined() => {
+            return Err(JsError::new_string(usage.to_string()))
+        }
+        _ => "greco_".to_string(),
+    };
+    if prefix.contains(['/', '\\']) {
+        return Err(JsError::new_str(
+            "temp prefix may not contain path separators",
+        ));
+    }
+    let dir = match args.get(1) {
+        Some(arg) if arg.is_string() => Some(arg.to_string()?),
+        Some(arg) if !arg.is_null_or_undefined() => {
+            return Err(JsError::new_string(usage.to_string()))
+        }
+        _ => None,
+    };
+    let permissions = permissions.clone();
+    fs_promise(
+        realm,
+        move || {
+            let dir = dir.unwrap_or_else(|| std::env::temp_dir().to_string_lossy().to_string());
+            let syscall = if is_dir { "mkdtemp" } else { "mkstemp" };
+            let real_dir = permissions.check(&dir, Access::Write, true, syscall)?;
+            tempfiles::TempPath::create(real_dir.as_path(), prefix.as_str(), is_dir)
+                .map_err(|e| FsError::from_io(e, syscall, &dir))
+        },
+        |realm, temp_path| {
+            let id = tempfiles::store_temp_path(temp_path);
+            realm.instantiate_proxy_with_id(&["greco", "io", "fs"], "TempPath", id)
+        },
+    )
+}
+
+/// createTempFile
+/// create an empty temp file which is removed when the returned TempPath is garbage collected
+/// # Example
+/// ```javascript
+/// async function temp_file_example() {
+///    let fs = await import('greco://fs');
+///    let tmp = await fs.createTempFile('export_');
+///    await fs.write(tmp.path, 'some data');
+/// }
+/// ```
+pub(crate) fn create_temp_file(
+    permissions: &Arc<FsPermissions>,
+    realm: &QuickJsRealmAdapter,
+    _this: &QuickJsValueAdapter,
+    args: &[QuickJsValueAdapter],
+) -> Result<QuickJsValueAdapter, JsError> {
+    create_temp_path(
+        permissions,
+        realm,
+        args,
+        false,
+        "createTempFile accepts two optional arguments: (prefix: String?, dir: String?)",
+    )
+}
+
+/// createTempDir
+/// create a temp dir which is removed (including its contents) when the returned TempPath is garbage collected
+pub(crate) fn create_temp_dir(
+    permissions: &Arc<FsPermissions>,
+    realm: &QuickJsRealmAdapter,
+    _this: &QuickJsValueAdapter,
+    args: &[QuickJsValueAdapter],
+) -> Result<QuickJsValueAdapter, JsError> {
+    create_temp_path(
+        permissions,
+        realm,
+        args,
+        true,
+        "createTempDir accepts two optional arguments: (prefix: String?, dir: String?)",
+    )
+}
+
+/// walk
+/// walk a dir tree, returns an FsWalker which is an async iterator of entries
+/// # Example
+/// ```javascript
+/// async function walk_example() {
+///    let fs = await import('greco://fs');
+///    for await (let entry of fs.walk('./logs', {include: '**/*.log'})) {
+///        if (entry.metadata.size > 1024 * 1024) {
+///            await fs.removeFile(entry.path);
+///        }
+///    }
+/// }
+/// ```
+pub(crate) fn walk(
+    permissions: &Arc<FsPermissions>,
+    realm: &QuickJsRealmAdapter,
+    _this: &QuickJsValueAdapter,
+    args: &[QuickJsValueAdapter],
+) -> Result<QuickJsValueAdapter, JsError> {
+    let root = string_arg(
+        args,
+        0,
+        "walk requires one or two arguments: (String, {maxDepth, followSymlinks, include, exclude}?)",
+    )?;
+    let options = walk::WalkOptions::parse(realm, args.get(1))?;
+    let id = walk::store_walker(walk::Walker::new(root, options, permissions.clone()));
+    realm.instantiate_proxy_with_id(&["greco", "io", "fs"], "FsWalker", id)
+}
+
+/// glob
+/// find all entries matching a glob pattern
+/// # Example
+/// ```javascript
+/// async function glob_example() {
+///    let fs = await import('greco://fs');
+///    for (let entry of await fs.glob('src/**/*.ts')) {
+///        console.log(entry.path);
+///    }
+/// }
+/// ```
+pub(crate) fn glob(
+    permissions: &Arc<FsPermissions>,
+    realm: &QuickJsRealmAdapter,
+    _this: &QuickJsValueAdapter,
+    args: &[QuickJsValueAdapter],
+) -> Result<QuickJsValueAdapter, JsError> {
+    let pattern = string_arg(args, 0, "glob requires one argument: (String)")?;
+    let (root, rel_pattern) = walk::split_glob(pattern.as_str());
+    let options = walk::WalkOptions {
+        // without ** we don't need to descend deeper than the number of segments
+        max_depth: if rel_pattern.contains("**") {
+            None
         } else {
-            args[1].stringify()
-        };
+            Some(rel_pattern.split('/').count())
+        },
+        follow_symlinks: false,
+        include: vec![walk::glob_to_regex(rel_pattern.as_str())?],
+        exclude: vec![],
+    };
+    let mut walker = walk::Walker::new(root, options, permissions.clone());
+    fs_promise(
+        realm,
+        move || {
+            let mut entries = vec![];
+            while let Some(entry) = walker.next_entry()? {
+                entries.push(entry);
+            }
+            Ok(entries)
+        },
+        |realm, entries| {
+            let arr = realm.create_array()?;
+            for entry in entries {
+                realm.push_array_element(&arr, &entry.to_js_value(realm)?)?;
+            }
+            Ok(arr)
+        },
+    )
+}
+
+/// watch
+/// watch a file or dir for changes, returns an FsWatcher which dispatches events
+/// # Example
+/// ```javascript
+/// async function watch_example() {
+///    let fs = await import('greco://fs');
+///    let watcher = fs.watch('./config', {recursive: false});
+///    watcher.addEventListener('modify', (evt) => {
+///        console.log('config changed: %s', evt.path);
+///    });
+/// }
+/// ```
+fn create_watch_function(
+    realm: &QuickJsRealmAdapter,
+    permissions: &Arc<FsPermissions>,
+) -> Result<QuickJsValueAdapter, JsError> {
+    let permissions = permissions.clone();
+    let native = realm.create_function(
+        "watch",
+        move |realm, _this, args| match watcher::start_watcher(&permissions, realm, args)? {
+            Ok(watcher) => Ok(watcher),
+            Err(fs_err) => fs_err.to_js_value(realm),
+        },
+        2,
+    )?;
+    // an Err returned from a native function is thrown as an Error without a code, so the native
+    // function returns the Error like fs_promise rejects with it and this wrapper throws it
+    let wrapper = realm.eval(Script::new(
+        "greco_fs_watch.js",
+        "(function(native) { return function watch(path, options) { \
+        const res = native(path, options); \
+        if (res instanceof Error) { throw res; } \
+        return res; }; })",
+    ))?;
+    realm.invoke_function(None, &wrapper, &[&native])
+}
+
+/// open
+/// open a file and resolve to a FileHandle
+/// # Example
+/// ```javascript
+/// async function open_example() {
+///    let fs = await import('greco://fs');
+///    let handle = await fs.open('./test.bin', 'r');
+///    let first_kb = await handle.read(1024);
+///    await handle.close();
+/// }
+/// ```
+pub(crate) fn open(
+    permissions: &Arc<FsPermissions>,
+    realm: &QuickJsRealmAdapter,
+    _this: &QuickJsValueAdapter,
+    args: &[QuickJsValueAdapter],
+) -> Result<QuickJsValueAdapter, JsError> {
+    let usage = "open requires one or two arguments: (String, String?)";
+    let path = string_arg(args, 0, usage)?;
+    let flags = match args.get(1) {
+        Some(arg) if arg.is_string() => arg.to_string()?,
+        Some(arg) if !arg.is_null_or_undefined() => return Err(JsError::new_str(usage)),
+        _ => "r".to_string(),
+    };
+    let mut options = fs::OpenOptions::new();
+    match flags.as_str() {
+        "r" => options.read(true),
+        "r+" => options.read(true).write(true),
+        "w" => options.write(true).create(true).truncate(true),
+        "w+" => options.read(true).write(true).create(true).truncate(true),
+        "a" => options.append(true).create(true),
+        "a+" => options.read(true).append(true).create(true),
+        _ => {
+            return Err(JsError::new_string(format!(
+            "open: unsupported flags {flags}, should be one of 'r', 'r+', 'w', 'w+', 'a' or 'a+'"
+        )))
+        }
+    };
+    let (read, write) = match flags.as_str() {
+        "r" => (true, false),
+        "w" | "a" => (false, true),
+        _ => (true, true),
+    };
+    let permissions = permissions.clone();
+    fs_promise(
+        realm,
+        move || {
+            let real = if write {
+                permissions.check(&path, Access::Write, true, "open")?
+            } else {
+                permissions.check(&path, Access::Read, true, "open")?
+            };
+            if read && write {
+                permissions.check(&path, Access::Read, true, "open")?;
+            }
+            options
+                .open(real)
+                .map_err(|e| FsError::from_io(e, "open", &path))
+        },
+        |realm, file| {
+            let id = filehandle::store_file_handle(file);
+            realm.instantiate_proxy_with_id(&["greco", "io", "fs"], "FileHandle", id)
+        },
+    )
+}
+
+/// the loader for the greco://fs module
+///
+/// the loader which is registered by init_greco_rt is unrestricted, to limit which paths scripts
+/// may access register a sandboxed loader on the builder before calling init_greco_rt, the first
+/// loader which has a module is used so a sandboxed loader which is registered after
+/// init_greco_rt is ignored
+///
+/// # Example
+///
+/// ```rust
+/// use quickjs_runtime::builder::QuickJsRuntimeBuilder;
+/// use green_copper_runtime::modules::io::fs::FsModuleLoader;
+/// let rtb = QuickJsRuntimeBuilder::new()
+///     .native_module_loader(FsModuleLoader::new().allow_read("./").allow_write("./target"));
+/// let rt = green_copper_runtime::init_greco_rt(rtb).build();
+/// ```
+pub struct FsModuleLoader {
+    permissions: Arc<FsPermissions>,
+}
 
-        match fs::write(path, content) {
-            Ok(_) => Ok(JsValueFacade::Null),
-            Err(e) => Err(JsError::new_string(format!("{e:?}"))),
+impl FsModuleLoader {
+    /// create a new sandboxed loader, scripts may not access any path until roots are added
+    /// with allow_read or allow_write
+    pub fn new() -> Self {
+        Self {
+            permissions: Arc::new(FsPermissions::default()),
         }
     }
+
+    /// create a loader which does not restrict access to any path
+    pub fn unrestricted() -> Self {
+        Self {
+            permissions: Arc::new(FsPermissions::unrestricted()),
+        }
+    }
+
+    /// allow scripts to read files and dirs in dir and its subdirs
+    /// # Panics
+    /// when dir does not exist, use try_allow_read to handle that
+    pub fn allow_read<P: AsRef<Path>>(self, dir: P) -> Self {
+        let dir = dir.as_ref();
+        self.try_allow_read(dir)
+            .unwrap_or_else(|e| panic!("could not allow read in {}: {}", dir.display(), e))
+    }
+
+    /// allow scripts to read files and dirs in dir and its subdirs
+    /// fails when dir does not exist
+    pub fn try_allow_read<P: AsRef<Path>>(mut self, dir: P) -> std::io::Result<Self> {
+        Arc::make_mut(&mut self.permissions).add_root(dir.as_ref(), Access::Read)?;
+        Ok(self)
+    }
+
+    /// allow scripts to create, write and remove files and dirs in dir and its subdirs
+    /// note that this does not imply read access
+    /// # Panics
+    /// when dir does not exist, use try_allow_write to handle that
+    pub fn allow_write<P: AsRef<Path>>(self, dir: P) -> Self {
+        let dir = dir.as_ref();
+        self.try_allow_write(dir)
+            .unwrap_or_else(|e| panic!("could not allow write in {}: {}", dir.display(), e))
+    }
+
+    /// allow scripts to create, write and remove files and dirs in dir and its subdirs
+    /// note that this does not imply read access
+    /// fails when dir does not exist
+    pub fn try_allow_write<P: AsRef<Path>>(mut self, dir: P) -> std::io::Result<Self> {
+        Arc::make_mut(&mut self.permissions).add_root(dir.as_ref(), Access::Write)?;
+        Ok(self)
+    }
 }
 
-pub struct FsModuleLoader {}
+impl FsModuleLoader {
+    pub(crate) fn permissions(&self) -> &Arc<FsPermissions> {
+        &self.permissions
+    }
+}
+
+impl Default for FsModuleLoader {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
 impl NativeModuleLoader for FsModuleLoader {
     fn has_module(&self, _realm: &QuickJsRealmAdapter, module_name: &str) -> bool {
@@ -196,15 +1317,29 @@ impl NativeModuleLoader for FsModuleLoader {
             "copy",
             "createSymlink",
             "createDirs",
+            "createTempDir",
+            "createTempFile",
+            "FileHandle",
+            "FileLock",
+            "FsWalker",
+            "FsWatcher",
             "getMetadata",
             "getSymlinkMetadata",
+            "glob",
             "list",
+            "lock",
+            "open",
+            "readBytes",
             "readString",
             "removeDir",
             "removeFile",
             "rename",
+            "TempPath",
             "touch",
+            "walk",
+            "watch",
             "write",
+            "writeBytes",
         ]
     }
 
@@ -213,68 +1348,124 @@ impl NativeModuleLoader for FsModuleLoader {
         realm: &QuickJsRealmAdapter,
         _module_name: &str,
     ) -> Vec<(&str, QuickJsValueAdapter)> {
-        init_exports(realm).expect("init fs exports failed")
+        init_exports(realm, &self.permissions).expect("init fs exports failed")
     }
 }
 
 pub(crate) fn init(builder: QuickJsRuntimeBuilder) -> QuickJsRuntimeBuilder {
-    builder.native_module_loader(FsModuleLoader {})
+    // a sandboxed loader which was added to the builder before this one takes precedence
+    builder.native_module_loader(FsModuleLoader::unrestricted())
+}
+
+fn create_fs_function<F>(
+    realm: &QuickJsRealmAdapter,
+    permissions: &Arc<FsPermissions>,
+    name: &'static str,
+    function: F,
+    arg_count: u32,
+) -> Result<(&'static str, QuickJsValueAdapter), JsError>
+where
+    F: Fn(
+            &Arc<FsPermissions>,
+            &QuickJsRealmAdapter,
+            &QuickJsValueAdapter,
+            &[QuickJsValueAdapter],
+        ) -> Result<QuickJsValueAdapter, JsError>
+        + 'static,
+{
+    let permissions = permissions.clone();
+    let func = realm.create_function(
+        name,
+        move |realm, this, args| function(&permissions, realm, this, args),
+        arg_count,
+    )?;
+    Ok((name, func))
 }
 
 fn init_exports(
     realm: &QuickJsRealmAdapter,
+    permissions: &Arc<FsPermissions>,
 ) -> Result<Vec<(&'static str, QuickJsValueAdapter)>, JsError> {
-    let copy_func = JsValueFacade::new_function("copy", copy, 1);
-    let write_func = JsValueFacade::new_function("write", write, 1);
-    let append_func = JsValueFacade::new_function("append", append, 1);
-    let create_symlink_func = JsValueFacade::new_function("createSymlink", create_symlink, 1);
-    let create_dirs_func = JsValueFacade::new_function("createDirs", create_dirs, 1);
-    let get_metadata_func = JsValueFacade::new_function("getMetadata", get_metadata, 1);
-    let get_symlink_metadata_func =
-        JsValueFacade::new_function("getSymlinkMetadata", get_symlink_metadata, 1);
-    let list_func = JsValueFacade::new_function("list", list, 1);
-    let remove_dir_func = JsValueFacade::new_function("removeDir", remove_dir, 1);
-    let rename_func = JsValueFacade::new_function("rename", rename, 1);
-    let touch_func = JsValueFacade::new_function("touch", touch, 1);
-    let remove_file_func = JsValueFacade::new_function("removeFile", remove_file, 1);
-    let read_string_func = JsValueFacade::new_function("readString", read_string, 1);
+    let file_handle_proxy_class =
+        realm.install_proxy(filehandle::create_file_handle_proxy(realm), false)?;
+
+    let watcher_proxy_class = realm.install_proxy(watcher::create_watcher_proxy(), false)?;
+
+    let walker_proxy_class = realm.install_proxy(walk::create_walker_proxy(realm), false)?;
+
+    let temp_path_proxy_class =
+        realm.install_proxy(tempfiles::create_temp_path_proxy(realm), false)?;
+
+    let file_lock_proxy_class = realm.install_proxy(lock::create_file_lock_proxy(realm), false)?;
 
     Ok(vec![
-        ("write", realm.from_js_value_facade(write_func)?),
-        (
+        ("FileHandle", file_handle_proxy_class),
+        ("FileLock", file_lock_proxy_class),
+        create_fs_function(realm, permissions, "lock", lock, 2)?,
+        ("TempPath", temp_path_proxy_class),
+        create_fs_function(realm, permissions, "createTempFile", create_temp_file, 2)?,
+        create_fs_function(realm, permissions, "createTempDir", create_temp_dir, 2)?,
+        ("FsWalker", walker_proxy_class),
+        ("FsWatcher", watcher_proxy_class),
+        create_fs_function(realm, permissions, "walk", walk, 2)?,
+        create_fs_function(realm, permissions, "glob", glob, 1)?,
+        ("watch", create_watch_function(realm, permissions)?),
+        create_fs_function(realm, permissions, "write", write, 3)?,
+        create_fs_function(realm, permissions, "writeBytes", write_bytes, 2)?,
+        create_fs_function(realm, permissions, "readBytes", read_bytes, 1)?,
+        create_fs_function(realm, permissions, "open", open, 2)?,
+        create_fs_function(
+            realm,
+            permissions,
             "getSymlinkMetadata",
-            realm.from_js_value_facade(get_symlink_metadata_func)?,
-        ),
-        ("copy", realm.from_js_value_facade(copy_func)?),
-        ("append", realm.from_js_value_facade(append_func)?),
-        (
-            "createSymlink",
-            realm.from_js_value_facade(create_symlink_func)?,
-        ),
-        ("createDirs", realm.from_js_value_facade(create_dirs_func)?),
-        (
-            "getMetadata",
-            realm.from_js_value_facade(get_metadata_func)?,
-        ),
-        ("list", realm.from_js_value_facade(list_func)?),
-        ("removeDir", realm.from_js_value_facade(remove_dir_func)?),
-        ("rename", realm.from_js_value_facade(rename_func)?),
-        ("touch", realm.from_js_value_facade(touch_func)?),
-        ("removeFile", realm.from_js_value_facade(remove_file_func)?),
-        ("readString", realm.from_js_value_facade(read_string_func)?),
+            get_symlink_metadata,
+            1,
+        )?,
+        create_fs_function(realm, permissions, "copy", copy, 2)?,
+        create_fs_function(realm, permissions, "append", append, 2)?,
+        create_fs_function(realm, permissions, "createSymlink", create_symlink, 2)?,
+        create_fs_function(realm, permissions, "createDirs", create_dirs, 1)?,
+        create_fs_function(realm, permissions, "getMetadata", get_metadata, 1)?,
+        create_fs_function(realm, permissions, "list", list, 1)?,
+        create_fs_function(realm, permissions, "removeDir", remove_dir, 2)?,
+        create_fs_function(realm, permissions, "rename", rename, 2)?,
+        create_fs_function(realm, permissions, "touch", touch, 1)?,
+        create_fs_function(realm, permissions, "removeFile", remove_file, 1)?,
+        create_fs_function(realm, permissions, "readString", read_string, 1)?,
     ])
 }
 
 #[cfg(test)]
 pub mod tests {
     use crate::init_greco_rt;
+    use crate::modules::io::fs::FsModuleLoader;
     use backtrace::Backtrace;
+    use futures::executor::block_on;
     use log::LevelFilter;
     use quickjs_runtime::builder::QuickJsRuntimeBuilder;
+    use quickjs_runtime::facades::QuickJsRuntimeFacade;
     use quickjs_runtime::jsutils::Script;
     use quickjs_runtime::values::JsValueFacade;
     use std::panic;
 
+    /// eval a script which evaluates to a Promise and return what that resolved to as a string
+    fn eval_to_string(rt: &QuickJsRuntimeFacade, name: &str, code: &str) -> String {
+        let res: JsValueFacade = block_on(rt.eval(None, Script::new(name, code)))
+            .ok()
+            .expect("script failed");
+        if let JsValueFacade::JsPromise { cached_promise } = res {
+            match block_on(cached_promise.get_promise_result())
+                .ok()
+                .expect("get prom res failed")
+            {
+                Ok(jsvf) => jsvf.get_str().to_string(),
+                Err(e) => panic!("prom rejected: {}", e.stringify()),
+            }
+        } else {
+            panic!("did not get a promise");
+        }
+    }
+
     #[test]
     fn test_fs() {
         panic::set_hook(Box::new(|panic_info| {
@@ -350,4 +1541,502 @@ pub mod tests {
             assert_eq!(s, "hello from greco fs");
         }
     }
+
+    #[test]
+    fn test_fs_ops() {
+        let rt = init_greco_rt(QuickJsRuntimeBuilder::new()).build();
+
+        let dir = std::env::temp_dir().join("greco_test_fs_ops");
+        let _ = std::fs::remove_dir_all(&dir);
+        let dir = dir.to_string_lossy().to_string();
+
+        let res = eval_to_string(
+            &rt,
+            "test_fs_ops.js",
+            format!(
+                r#"
+            async function test() {{
+                let fs = await import('greco://fs');
+                const dir = '{dir}';
+                await fs.createDirs(dir + '/sub');
+                await fs.write(dir + '/a.txt', 'hello');
+                await fs.append(dir + '/a.txt', ' world');
+                await fs.copy(dir + '/a.txt', dir + '/b.txt');
+                await fs.rename(dir + '/b.txt', dir + '/sub/c.txt');
+                await fs.touch(dir + '/d.txt');
+                await fs.createSymlink(dir + '/a.txt', dir + '/e.txt');
+
+                const names = await fs.list(dir);
+                if (names.join(',') !== 'a.txt,d.txt,e.txt,sub') {{
+                    throw Error('unexpected list result ' + names.join(','));
+                }}
+
+                const meta = await fs.getMetadata(dir + '/a.txt');
+                if (meta.size !== 11 || !meta.isFile || meta.isDir || !(meta.mtime instanceof Date)) {{
+                    throw Error('unexpected metadata ' + JSON.stringify(meta));
+                }}
+                const linkMeta = await fs.getSymlinkMetadata(dir + '/e.txt');
+                if (!linkMeta.isSymlink) {{
+                    throw Error('e.txt was not a symlink');
+                }}
+
+                let code = null;
+                try {{
+                    await fs.removeDir(dir);
+                }} catch(ex) {{
+                    code = ex.code;
+                }}
+                if (code !== 'ENOTEMPTY') {{
+                    throw Error('unexpected error code ' + code);
+                }}
+
+                // the destination dir does not exist, the error names both paths
+                try {{
+                    await fs.copy(dir + '/a.txt', dir + '/missing/a.txt');
+                    throw Error('copy to a missing dir did not fail');
+                }} catch(ex) {{
+                    if (ex.code !== 'ENOENT' || ex.dest !== dir + '/missing/a.txt'
+                        || !ex.message.endsWith(`copyfile '${{dir}}/a.txt' -> '${{dir}}/missing/a.txt'`)) {{
+                        throw Error('unexpected copy error ' + ex.message);
+                    }}
+                }}
+
+                await fs.removeDir(dir, {{recursive: true}});
+
+                try {{
+                    await fs.readString(dir + '/a.txt');
+                }} catch(ex) {{
+                    return ex.code;
+                }}
+                throw Error('read of removed file did not fail');
+            }}
+
+            test()
+
+            "#
+            )
+            .as_str(),
+        );
+        assert_eq!(res, "ENOENT");
+    }
+
+    #[test]
+    fn test_fs_bytes() {
+        let rt = init_greco_rt(QuickJsRuntimeBuilder::new()).build();
+
+        let dir = std::env::temp_dir().join("greco_test_fs_bytes");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).expect("could not create test dir");
+        let dir = dir.to_string_lossy().to_string();
+
+        let res = eval_to_string(
+            &rt,
+            "test_fs_bytes.js",
+            format!(
+                r#"
+            async function test() {{
+                let fs = await import('greco://fs');
+                const dir = '{dir}';
+                await fs.writeBytes(dir + '/a.bin', new Uint8Array([1, 2, 3, 4, 5]));
+                const bytes = await fs.readBytes(dir + '/a.bin');
+                if (!(bytes instanceof Uint8Array) || bytes.join(',') !== '1,2,3,4,5') {{
+                    throw Error('unexpected bytes ' + bytes);
+                }}
+
+                let failed = false;
+                try {{
+                    await fs.write(dir + '/b.txt', {{a: 1}});
+                }} catch(ex) {{
+                    failed = true;
+                }}
+                if (!failed) {{
+                    throw Error('write of an object did not fail');
+                }}
+
+                const handle = await fs.open(dir + '/c.bin', 'w+');
+                const written = await handle.write(new Uint8Array([10, 20, 30, 40]));
+                await handle.write('AB');
+                await handle.sync();
+                await handle.seek(1);
+                const chunk = await handle.read(2);
+                const pos = await handle.seek(-1, 'end');
+                let negative = null;
+                try {{
+                    await handle.seek(-1, 'start');
+                }} catch(ex) {{
+                    negative = ex.message;
+                }}
+                await handle.truncate(3);
+                await handle.close();
+
+                let code = null;
+                try {{
+                    await handle.read(1);
+                }} catch(ex) {{
+                    code = ex.code;
+                }}
+
+                const meta = await fs.getMetadata(dir + '/c.bin');
+                await fs.removeDir(dir, {{recursive: true}});
+                return [written, chunk.join(','), pos, meta.size, code, negative].join('|');
+            }}
+
+            test()
+
+            "#
+            )
+            .as_str(),
+        );
+        assert_eq!(
+            res,
+            "4|20,30|5|3|EBADF|seek: offset from 'start' should not be negative, got -1"
+        );
+    }
+
+    #[test]
+    fn test_fs_sandbox() {
+        let base = std::env::temp_dir().join("greco_test_fs_sandbox");
+        let _ = std::fs::remove_dir_all(&base);
+        std::fs::create_dir_all(base.join("ro")).expect("could not create test dir");
+        std::fs::create_dir_all(base.join("rw")).expect("could not create test dir");
+        std::fs::write(base.join("secret.txt"), "secret").expect("could not write secret");
+        std::fs::write(base.join("ro/a.txt"), "hello").expect("could not write a.txt");
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(base.join("secret.txt"), base.join("rw/link.txt"))
+            .expect("could not create symlink");
+
+        let rtb = QuickJsRuntimeBuilder::new().native_module_loader(
+            FsModuleLoader::new()
+                .allow_read(base.join("ro"))
+                .allow_read(base.join("rw"))
+                .allow_write(base.join("rw")),
+        );
+        let rt = init_greco_rt(rtb).build();
+        let base = base.to_string_lossy().to_string();
+
+        let res = eval_to_string(
+            &rt,
+            "test_fs_sandbox.js",
+            format!(
+                r#"
+            async function code(prom) {{
+                try {{
+                    await prom;
+                    return 'OK';
+                }} catch(ex) {{
+                    return ex.code;
+                }}
+            }}
+            async function test() {{
+                let fs = await import('greco://fs');
+                const base = '{base}';
+                return [
+                    await code(fs.readString(base + '/ro/a.txt')),
+                    await code(fs.readString(base + '/secret.txt')),
+                    await code(fs.readString(base + '/ro/../secret.txt')),
+                    await code(fs.write(base + '/ro/b.txt', 'b')),
+                    await code(fs.write(base + '/rw/b.txt', 'b')),
+                    await code(fs.copy(base + '/ro/a.txt', base + '/rw/c.txt')),
+                    await code(fs.rename(base + '/rw/c.txt', base + '/d.txt')),
+                    await code(fs.readString(base + '/rw/link.txt')),
+                    await code(fs.removeFile(base + '/rw/link.txt')),
+                    await code(fs.open(base + '/ro/a.txt', 'r+')),
+                ].join(',');
+            }}
+
+            test()
+
+            "#
+            )
+            .as_str(),
+        );
+        assert_eq!(res, "OK,EACCES,EACCES,EACCES,OK,OK,EACCES,EACCES,OK,EACCES");
+        let _ = std::fs::remove_dir_all(std::env::temp_dir().join("greco_test_fs_sandbox"));
+    }
+
+    #[test]
+    fn test_fs_sandbox_loader_order() {
+        let base = std::env::temp_dir().join("greco_test_fs_sandbox_order");
+        let _ = std::fs::remove_dir_all(&base);
+        std::fs::create_dir_all(base.join("allowed")).expect("could not create test dir");
+        std::fs::write(base.join("secret.txt"), "secret").expect("could not write secret");
+
+        assert!(FsModuleLoader::new()
+            .try_allow_read(base.join("missing"))
+            .is_err());
+
+        let read_secret = |rtb: QuickJsRuntimeBuilder| -> String {
+            let rt = rtb.build();
+            let secret = base.join("secret.txt").to_string_lossy().to_string();
+            eval_to_string(
+                &rt,
+                "test_fs_sandbox_order.js",
+                format!(
+                    r#"
+                async function test() {{
+                    let fs = await import('greco://fs');
+                    try {{
+                        return await fs.readString('{secret}');
+                    }} catch(ex) {{
+                        return ex.code;
+                    }}
+                }}
+
+                test()
+
+                "#
+                )
+                .as_str(),
+            )
+        };
+
+        let sandbox = || {
+            FsModuleLoader::new()
+                .try_allow_read(base.join("allowed"))
+                .expect("could not allow read")
+        };
+
+        // the sandboxed loader is added first so it wins from the unrestricted one of init_greco_rt
+        let rtb = QuickJsRuntimeBuilder::new().native_module_loader(sandbox());
+        assert_eq!(read_secret(init_greco_rt(rtb)), "EACCES");
+
+        // added after init_greco_rt the sandboxed loader is never used
+        let rtb = init_greco_rt(QuickJsRuntimeBuilder::new()).native_module_loader(sandbox());
+        assert_eq!(read_secret(rtb), "secret");
+
+        let _ = std::fs::remove_dir_all(&base);
+    }
+
+    #[test]
+    fn test_fs_watch() {
+        let rt = init_greco_rt(QuickJsRuntimeBuilder::new()).build();
+
+        let dir = std::env::temp_dir().join("greco_test_fs_watch");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).expect("could not create test dir");
+        let dir = dir.to_string_lossy().to_string();
+
+        let res = eval_to_string(
+            &rt,
+            "test_fs_watch.js",
+            format!(
+                r#"
+            async function test() {{
+                let fs = await import('greco://fs');
+                const dir = '{dir}';
+                let watcher = fs.watch(dir, {{recursive: true}});
+                let created = new Promise((resolve) => {{
+                    watcher.addEventListener('create', (evt) => {{
+                        resolve(evt.path);
+                    }});
+                }});
+                await fs.write(dir + '/new.txt', 'hi');
+                let path = await created;
+                watcher.close();
+                let missing = '';
+                try {{
+                    fs.watch(dir + '/missing');
+                }} catch(ex) {{
+                    missing = ex instanceof Error ? ex.code : 'not an Error';
+                }}
+                await fs.removeDir(dir, {{recursive: true}});
+                return missing + '|' + path;
+            }}
+
+            test()
+
+            "#
+            )
+            .as_str(),
+        );
+        assert!(res.starts_with("ENOENT|"));
+        assert!(res.ends_with("new.txt"));
+    }
+
+    #[test]
+    fn test_fs_walk() {
+        let rt = init_greco_rt(QuickJsRuntimeBuilder::new()).build();
+
+        let dir = std::env::temp_dir().join("greco_test_fs_walk");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("src/lib/deep")).expect("could not create test dir");
+        std::fs::create_dir_all(dir.join("src/node_modules")).expect("could not create test dir");
+        for file in [
+            "src/main.ts",
+            "src/readme.md",
+            "src/lib/util.ts",
+            "src/lib/deep/deeper.ts",
+            "src/node_modules/dep.ts",
+        ] {
+            std::fs::write(dir.join(file), "export {}").expect("could not write test file");
+        }
+        let dir = dir.to_string_lossy().to_string();
+
+        let res = eval_to_string(
+            &rt,
+            "test_fs_walk.js",
+            format!(
+                r#"
+            async function test() {{
+                let fs = await import('greco://fs');
+                const dir = '{dir}';
+                let walked = [];
+                for await (let entry of fs.walk(dir + '/src', {{exclude: 'node_modules', include: '**/*.ts', maxDepth: 2}})) {{
+                    if (entry.metadata.size !== 9 || !entry.metadata.isFile) {{
+                        throw Error('unexpected metadata for ' + entry.path);
+                    }}
+                    walked.push(entry.depth + ':' + entry.name);
+                }}
+                let globbed = (await fs.glob(dir + '/src/**/*.ts')).map((e) => e.path.substring(dir.length));
+                let invalid = [];
+                for (const maxDepth of [-1, 1.5, NaN, '2']) {{
+                    try {{
+                        fs.walk(dir + '/src', {{maxDepth}});
+                        invalid.push('accepted');
+                    }} catch(ex) {{
+                        invalid.push('rejected');
+                    }}
+                }}
+                await fs.removeDir(dir, {{recursive: true}});
+                return walked.join(',') + '|' + globbed.join(',') + '|' + invalid.join(',');
+            }}
+
+            test()
+
+            "#
+            )
+            .as_str(),
+        );
+        assert_eq!(
+            res,
+            "2:util.ts,1:main.ts|/src/lib/deep/deeper.ts,/src/lib/util.ts,/src/main.ts,/src/node_modules/dep.ts|rejected,rejected,rejected,rejected"
+        );
+    }
+
+    #[test]
+    fn test_fs_temp() {
+        let rt = init_greco_rt(QuickJsRuntimeBuilder::new()).build();
+
+        let res = eval_to_string(
+            &rt,
+            "test_fs_temp.js",
+            r#"
+            async function test() {
+                let fs = await import('greco://fs');
+                let tmp_file = await fs.createTempFile('greco_test_');
+                await fs.write(tmp_file.path, 'hello');
+                let tmp_dir = await fs.createTempDir('greco_test_');
+                await fs.write(tmp_dir.path + '/a.txt', 'hello');
+                let dir_meta = await fs.getMetadata(tmp_dir.path);
+                if (!dir_meta.isDir) {
+                    throw Error('temp dir was not a dir');
+                }
+                await tmp_dir.remove();
+                let code = null;
+                try {
+                    await fs.getMetadata(tmp_dir.path);
+                } catch(ex) {
+                    code = ex.code;
+                }
+                // no reference to this one is kept so it is removed by gc
+                let gc_path = (await fs.createTempFile('greco_test_')).path;
+                // these are removed when the realm is destroyed
+                globalThis.keptFile = tmp_file;
+                globalThis.keptDir = await fs.createTempDir('greco_test_');
+                await fs.write(keptDir.path + '/a.txt', 'hello');
+                return [code, gc_path, tmp_file.path, keptDir.path].join('|');
+            }
+
+            test()
+
+            "#,
+        );
+        let mut parts = res.split('|');
+        assert_eq!(parts.next(), Some("ENOENT"));
+        let paths: Vec<&str> = parts.collect();
+
+        // dirs are removed in a helper thread so poll for a bounded time
+        let assert_removed = |path: &str| {
+            let path = std::path::Path::new(path);
+            let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+            while path.exists() && std::time::Instant::now() < deadline {
+                std::thread::sleep(std::time::Duration::from_millis(10));
+            }
+            assert!(!path.exists(), "{} was not removed", path.display());
+        };
+
+        let (gc_path, realm_paths) = paths.split_first().expect("unexpected result");
+        assert!(realm_paths
+            .iter()
+            .all(|path| std::path::Path::new(path).exists()));
+        rt.gc_sync();
+        assert_removed(gc_path);
+
+        drop(rt);
+        for path in realm_paths {
+            assert_removed(path);
+        }
+    }
+
+    #[test]
+    fn test_fs_atomic_write_and_lock() {
+        let rt = init_greco_rt(QuickJsRuntimeBuilder::new()).build();
+
+        let dir = std::env::temp_dir().join("greco_test_fs_lock");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).expect("could not create test dir");
+        let dir = dir.to_string_lossy().to_string();
+
+        let res = eval_to_string(
+            &rt,
+            "test_fs_lock.js",
+            format!(
+                r#"
+            async function test() {{
+                let fs = await import('greco://fs');
+                const dir = '{dir}';
+                await fs.write(dir + '/state.json', '{{"a": 1}}');
+                await fs.write(dir + '/state.json', '{{"a": 2}}', {{atomic: true}});
+                const state = JSON.parse(await fs.readString(dir + '/state.json'));
+                const names = await fs.list(dir);
+
+                // a new file written atomically gets the same mode as one created by a plain write
+                await fs.write(dir + '/plain.json', '{{}}');
+                await fs.write(dir + '/new.json', '{{}}', {{atomic: true}});
+                const plainMode = (await fs.getMetadata(dir + '/plain.json')).mode & 0o777;
+                const atomicMode = (await fs.getMetadata(dir + '/new.json')).mode & 0o777;
+                const modes = plainMode === atomicMode ? 'same' : plainMode.toString(8) + '!=' + atomicMode.toString(8);
+
+                let shared1 = await fs.lock(dir + '/state.lock', {{exclusive: false}});
+                let shared2 = await fs.lock(dir + '/state.lock', {{exclusive: false}});
+                let order = [];
+                let exclusive = fs.lock(dir + '/state.lock').then((l) => {{
+                    order.push('exclusive');
+                    return l;
+                }});
+                order.push('shared');
+                for (const opts of [{{wait: false}}, {{timeoutMs: 50}}]) {{
+                    try {{
+                        (await fs.lock(dir + '/state.lock', opts)).close();
+                        order.push('locked');
+                    }} catch (ex) {{
+                        order.push(ex.code);
+                    }}
+                }}
+                shared1.close();
+                shared2.close();
+                (await exclusive).close();
+
+                await fs.removeDir(dir, {{recursive: true}});
+                return state.a + '|' + names.join(',') + '|' + order.join(',') + '|' + modes;
+            }}
+
+            test()
+
+            "#
+            )
+            .as_str(),
+        );
+        assert_eq!(res, "2|state.json|shared,EAGAIN,ETIMEDOUT,exclusive|same");
+    }
 }
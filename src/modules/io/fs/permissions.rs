@@ -0,0 +1,121 @@
+//! sandbox roots for the greco://fs module
+//!
+//! every path passed to the fs module is resolved to an absolute path (following symlinks) and
+//! checked against the roots which were configured with [FsModuleLoader::allow_read](../struct.FsModuleLoader.html#method.allow_read)
+//! and [FsModuleLoader::allow_write](../struct.FsModuleLoader.html#method.allow_write)
+//!
+
+use crate::modules::io::fs::FsError;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// max number of symlinks to follow when resolving a path that does not exist (yet)
+const MAX_LINK_DEPTH: usize = 40;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum Access {
+    Read,
+    Write,
+}
+
+#[derive(Clone, Default)]
+pub(crate) struct FsPermissions {
+    unrestricted: bool,
+    read_roots: Vec<PathBuf>,
+    write_roots: Vec<PathBuf>,
+}
+
+impl FsPermissions {
+    pub(crate) fn unrestricted() -> Self {
+        Self {
+            unrestricted: true,
+            ..Default::default()
+        }
+    }
+
+    pub(crate) fn add_root(&mut self, dir: &Path, access: Access) -> io::Result<()> {
+        let root = dir.canonicalize()?;
+        log::trace!("FsPermissions::add_root {:?} {:?}", access, root);
+        match access {
+            Access::Read => self.read_roots.push(root),
+            Access::Write => self.write_roots.push(root),
+        }
+        Ok(())
+    }
+
+    /// resolve a path as passed from script and check if it is within one of the roots for access
+    /// when follow_symlinks is false the last component is not resolved so a symlink itself may be
+    /// inspected, renamed or removed
+    /// returns the resolved path which should be used for the actual operation
+    pub(crate) fn check(
+        &self,
+        path: &str,
+        access: Access,
+        follow_symlinks: bool,
+        syscall: &str,
+    ) -> Result<PathBuf, FsError> {
+        if self.unrestricted {
+            return Ok(PathBuf::from(path));
+        }
+        let resolved = resolve(Path::new(path), follow_symlinks, 0)
+            .map_err(|e| FsError::from_io(e, syscall, path))?;
+        let roots = match access {
+            Access::Read => &self.read_roots,
+            Access::Write => &self.write_roots,
+        };
+        if roots.iter().any(|root| resolved.starts_with(root)) {
+            Ok(resolved)
+        } else {
+            log::debug!(
+                "FsPermissions::check denied {:?} access to {}",
+                access,
+                resolved.display()
+            );
+            Err(FsError {
+                code: "EACCES",
+                message: format!(
+                    "EACCES: path is outside of the allowed roots, {syscall} '{path}'"
+                ),
+                path: Some(path.to_string()),
+                dest: None,
+            })
+        }
+    }
+}
+
+/// resolve a path which may not exist yet, the nearest existing ancestor is canonicalized and the
+/// remaining components are appended to it
+fn resolve(path: &Path, follow_symlinks: bool, depth: usize) -> io::Result<PathBuf> {
+    if depth > MAX_LINK_DEPTH {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "too many levels of symbolic links",
+        ));
+    }
+    let path = if path.is_absolute() {
+        path.to_path_buf()
+    } else {
+        std::env::current_dir()?.join(path)
+    };
+    if follow_symlinks {
+        if let Ok(canonical) = path.canonicalize() {
+            return Ok(canonical);
+        }
+        // a dangling symlink, resolve its target so we don't write through it to outside the roots
+        if let Ok(meta) = path.symlink_metadata() {
+            if meta.file_type().is_symlink() {
+                let target = std::fs::read_link(&path)?;
+                let target = match path.parent() {
+                    Some(parent) if target.is_relative() => parent.join(target),
+                    _ => target,
+                };
+                return resolve(target.as_path(), true, depth + 1);
+            }
+        }
+    }
+    match (path.parent(), path.file_name()) {
+        (Some(parent), Some(name)) => Ok(resolve(parent, true, depth)?.join(name)),
+        // the root dir or a path ending in '..', those should exist
+        _ => path.canonicalize(),
+    }
+}
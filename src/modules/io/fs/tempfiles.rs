@@ -0,0 +1,238 @@
+//! TempPath proxy
+//!
+//! a TempPath is obtained by calling createTempFile() or createTempDir() in the greco://fs module,
+//! the file or dir (and its contents) is removed when the TempPath is garbage collected or when its
+//! realm is destroyed
+//!
+//! # Example
+//!
+//! ```javascript
+//! async function test() {
+//!     let fs = await import('greco://fs');
+//!     let tmp_dir = await fs.createTempDir('report_');
+//!     await fs.write(tmp_dir.path + '/report.csv', 'a;b;c');
+//!     // remove now instead of waiting for gc
+//!     await tmp_dir.remove();
+//! }
+//! ```
+//!
+//! # Methods
+//!
+//! ##path: string
+//! ##remove(): Promise<void>
+//!
+
+use crate::modules::io::fs::fs_promise;
+use crate::modules::io::fs::FsError;
+use hirofa_utils::auto_id_map::AutoIdMap;
+use quickjs_runtime::jsutils::helper_tasks::add_helper_task_async;
+use quickjs_runtime::jsutils::jsproxies::JsProxy;
+use quickjs_runtime::jsutils::JsError;
+use quickjs_runtime::quickjsrealmadapter::QuickJsRealmAdapter;
+use std::cell::RefCell;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+static TEMP_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    static TEMP_PATHS: RefCell<AutoIdMap<Arc<TempPath>>> = RefCell::new(AutoIdMap::new());
+}
+
+/// a temp file or dir which is removed when dropped
+pub(crate) struct TempPath {
+    path: PathBuf,
+    is_dir: bool,
+    removed: Mutex<bool>,
+}
+
+impl TempPath {
+    /// create a new temp file or dir with a unique name starting with prefix in dir, only the
+    /// current user may read or write it
+    pub(crate) fn create(dir: &Path, prefix: &str, is_dir: bool) -> std::io::Result<Self> {
+        let mode = if is_dir { 0o700 } else { 0o600 };
+        Self::create_with_mode(dir, prefix, is_dir, mode)
+    }
+
+    /// create a new temp file or dir like create but with the given unix mode, the umask of the
+    /// process still applies, the mode is ignored on other platforms
+    pub(crate) fn create_with_mode(
+        dir: &Path,
+        prefix: &str,
+        is_dir: bool,
+        #[cfg_attr(not(unix), allow(unused_variables))] mode: u32,
+    ) -> std::io::Result<Self> {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.subsec_nanos())
+            .unwrap_or(0);
+        loop {
+            let count = TEMP_COUNTER.fetch_add(1, Ordering::SeqCst);
+            let name = format!("{prefix}{:x}{nanos:x}{count:x}", std::process::id());
+            let path = dir.join(name);
+            let res = if is_dir {
+                let mut builder = fs::DirBuilder::new();
+                #[cfg(unix)]
+                {
+                    use std::os::unix::fs::DirBuilderExt;
+                    builder.mode(mode);
+                }
+                builder.create(&path)
+            } else {
+                let mut options = fs::OpenOptions::new();
+                options.write(true).create_new(true);
+                #[cfg(unix)]
+                {
+                    use std::os::unix::fs::OpenOptionsExt;
+                    options.mode(mode);
+                }
+                options.open(&path).map(|_| ())
+            };
+            match res {
+                Ok(_) => {
+                    return Ok(Self {
+                        path,
+                        is_dir,
+                        removed: Mutex::new(false),
+                    })
+                }
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        self.path.as_path()
+    }
+
+    /// rename the temp file or dir to path, it will no longer be removed when dropped
+    pub(crate) fn persist(self, path: &Path) -> std::io::Result<()> {
+        let removed = &mut *self.removed.lock().unwrap();
+        fs::rename(&self.path, path)?;
+        *removed = true;
+        Ok(())
+    }
+
+    fn remove(&self) -> std::io::Result<()> {
+        let removed = &mut *self.removed.lock().unwrap();
+        if !*removed {
+            if self.is_dir {
+                fs::remove_dir_all(&self.path)?;
+            } else {
+                fs::remove_file(&self.path)?;
+            }
+            *removed = true;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for TempPath {
+    fn drop(&mut self) {
+        if let Err(e) = self.remove() {
+            if e.kind() != ErrorKind::NotFound {
+                log::error!("could not remove temp path {}: {}", self.path.display(), e);
+            }
+        }
+    }
+}
+
+pub(crate) fn store_temp_path(temp_path: TempPath) -> usize {
+    TEMP_PATHS.with(|rc| {
+        let map = &mut *rc.borrow_mut();
+        map.insert(Arc::new(temp_path))
+    })
+}
+
+fn with_temp_path(instance_id: &usize) -> Result<Arc<TempPath>, JsError> {
+    TEMP_PATHS.with(|rc| {
+        let map = &*rc.borrow();
+        map.get(instance_id)
+            .cloned()
+            .ok_or_else(|| JsError::new_str("no such TempPath"))
+    })
+}
+
+fn drop_temp_path(instance_id: &usize) {
+    let arc = TEMP_PATHS.with(|rc| {
+        let map = &mut *rc.borrow_mut();
+        map.remove(instance_id)
+    });
+    if arc.is_dir {
+        // removing a dir may take a while, don't do that in the js thread
+        let _unused = add_helper_task_async(async move {
+            drop(arc);
+        });
+    } else {
+        // a file is removed right away so it is also removed when the runtime is being dropped
+        drop(arc);
+    }
+}
+
+pub(crate) fn create_temp_path_proxy(_realm: &QuickJsRealmAdapter) -> JsProxy {
+    JsProxy::new()
+        .namespace(&["greco", "io", "fs"])
+        .name("TempPath")
+        .getter("path", |_rt, realm, instance_id| {
+            let temp_path = with_temp_path(instance_id)?;
+            realm.create_string(temp_path.path().to_string_lossy().as_ref())
+        })
+        .method("remove", |_rt, realm, instance_id, _args| {
+            let temp_path = with_temp_path(instance_id)?;
+            fs_promise(
+                realm,
+                move || {
+                    temp_path.remove().map_err(|e| {
+                        FsError::from_io(e, "unlink", temp_path.path().to_string_lossy().as_ref())
+                    })
+                },
+                |realm, _| realm.create_null(),
+            )
+        })
+        .finalizer(|_rt, _realm, instance_id| {
+            drop_temp_path(&instance_id);
+        })
+}
+
+#[cfg(test)]
+pub mod tests {
+    use crate::modules::io::fs::tempfiles::TempPath;
+
+    #[test]
+    fn test_temp_path() {
+        let dir = std::env::temp_dir();
+
+        let temp_file = TempPath::create(&dir, "greco_test_", false).expect("create file failed");
+        let temp_dir = TempPath::create(&dir, "greco_test_", true).expect("create dir failed");
+        std::fs::write(temp_dir.path().join("a.txt"), "hello").expect("write failed");
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = |p: &std::path::Path| {
+                std::fs::metadata(p)
+                    .expect("metadata failed")
+                    .permissions()
+                    .mode()
+                    & 0o777
+            };
+            assert_eq!(mode(temp_file.path()), 0o600);
+            assert_eq!(mode(temp_dir.path()), 0o700);
+        }
+
+        let file_path = temp_file.path().to_path_buf();
+        let dir_path = temp_dir.path().to_path_buf();
+        assert!(file_path.exists());
+        assert!(dir_path.exists());
+
+        drop(temp_file);
+        drop(temp_dir);
+        assert!(!file_path.exists());
+        assert!(!dir_path.exists());
+    }
+}
@@ -0,0 +1,425 @@
+//! walk and glob
+//!
+//! walk() returns an FsWalker which is an async iterator of entries, glob() resolves to an Array
+//! of entries
+//!
+//! # Example
+//!
+//! ```javascript
+//! async function test() {
+//!     let fs = await import('greco://fs');
+//!     for await (let entry of fs.walk('./src', {maxDepth: 3, exclude: ['**/node_modules']})) {
+//!         console.log("%s is %s bytes", entry.path, entry.metadata.size);
+//!     }
+//!     let ts_files = await fs.glob('src/**/*.ts');
+//! }
+//! ```
+//!
+//! # Entries
+//!
+//! ```javascript
+//! {
+//!     path: 'src/lib/util.ts',  // the root path joined with the relative path of the entry
+//!     name: 'util.ts',
+//!     depth: 2,                 // 1 for entries directly in root
+//!     metadata: {}              // the same object getMetadata resolves to
+//! }
+//! ```
+//!
+//! # Options
+//!
+//! * maxDepth: number, a non negative integer, max depth to descend to, unlimited by default
+//! * followSymlinks: boolean, descend into symlinked dirs and report the metadata of link targets, false by default
+//! * include: string | Array<string>, glob patterns, only entries whose path relative to root matches one of these are returned
+//! * exclude: string | Array<string>, glob patterns, entries whose path relative to root matches one of these are skipped, excluded dirs are not descended into
+//!
+//! glob patterns support `*`, `**`, `?`, `[abc]` and `{a,b}`
+//!
+
+use crate::modules::io::fs::permissions::{Access, FsPermissions};
+use crate::modules::io::fs::{bool_option, fs_promise, number_arg, FileStats, FsError};
+use hirofa_utils::auto_id_map::AutoIdMap;
+use quickjs_runtime::jsutils::jsproxies::JsProxy;
+use quickjs_runtime::jsutils::JsError;
+use quickjs_runtime::quickjsrealmadapter::QuickJsRealmAdapter;
+use quickjs_runtime::quickjsvalueadapter::QuickJsValueAdapter;
+use regex::Regex;
+use std::cell::RefCell;
+use std::collections::{HashSet, VecDeque};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+thread_local! {
+    static WALKERS: RefCell<AutoIdMap<Arc<Mutex<Walker>>>> = RefCell::new(AutoIdMap::new());
+}
+
+pub(crate) struct WalkOptions {
+    pub(crate) max_depth: Option<usize>,
+    pub(crate) follow_symlinks: bool,
+    pub(crate) include: Vec<Regex>,
+    pub(crate) exclude: Vec<Regex>,
+}
+
+impl WalkOptions {
+    pub(crate) fn parse(
+        realm: &QuickJsRealmAdapter,
+        options: Option<&QuickJsValueAdapter>,
+    ) -> Result<Self, JsError> {
+        let mut res = Self {
+            max_depth: None,
+            follow_symlinks: bool_option(realm, options, "followSymlinks")?,
+            include: vec![],
+            exclude: vec![],
+        };
+        if let Some(options) = options.filter(|o| o.is_object()) {
+            let max_depth = realm.get_object_property(options, "maxDepth")?;
+            if max_depth.is_i32() || max_depth.is_f64() {
+                let val = number_arg(&max_depth);
+                if val.fract() != 0.0 || val < 0.0 || !val.is_finite() {
+                    return Err(JsError::new_string(format!(
+                        "maxDepth should be a non negative integer, got {val}"
+                    )));
+                }
+                res.max_depth = Some(val as usize);
+            } else if !max_depth.is_null_or_undefined() {
+                return Err(JsError::new_str("maxDepth should be a number"));
+            }
+            res.include = glob_list(realm, &realm.get_object_property(options, "include")?)?;
+            res.exclude = glob_list(realm, &realm.get_object_property(options, "exclude")?)?;
+        }
+        Ok(res)
+    }
+}
+
+/// parse a string or Array<string> of glob patterns
+fn glob_list(
+    realm: &QuickJsRealmAdapter,
+    value: &QuickJsValueAdapter,
+) -> Result<Vec<Regex>, JsError> {
+    if value.is_string() {
+        Ok(vec![glob_to_regex(value.to_string()?.as_str())?])
+    } else if value.is_array() {
+        let mut res = vec![];
+        for x in 0..realm.get_array_length(value)? {
+            let pattern = realm.get_array_element(value, x)?;
+            if !pattern.is_string() {
+                return Err(JsError::new_str("glob patterns should be strings"));
+            }
+            res.push(glob_to_regex(pattern.to_string()?.as_str())?);
+        }
+        Ok(res)
+    } else if value.is_null_or_undefined() {
+        Ok(vec![])
+    } else {
+        Err(JsError::new_str(
+            "glob patterns should be a string or an Array of strings",
+        ))
+    }
+}
+
+/// translate a glob pattern to a Regex which matches a whole relative path
+pub(crate) fn glob_to_regex(pattern: &str) -> Result<Regex, JsError> {
+    let mut re = String::from("^");
+    let mut chars = pattern.chars().peekable();
+    let mut brace_depth = 0;
+    while let Some(c) = chars.next() {
+        match c {
+            '*' => {
+                if chars.peek() == Some(&'*') {
+                    chars.next();
+                    if chars.peek() == Some(&'/') {
+                        chars.next();
+                        re.push_str("(?:.*/)?");
+                    } else {
+                        re.push_str(".*");
+                    }
+                } else {
+                    re.push_str("[^/]*");
+                }
+            }
+            '?' => re.push_str("[^/]"),
+            '[' => {
+                re.push('[');
+                if chars.peek() == Some(&'!') {
+                    chars.next();
+                    re.push('^');
+                }
+                for c in chars.by_ref() {
+                    if c == ']' {
+                        break;
+                    }
+                    if c == '\\' || c == '[' {
+                        re.push('\\');
+                    }
+                    re.push(c);
+                }
+                re.push(']');
+            }
+            '{' => {
+                brace_depth += 1;
+                re.push_str("(?:");
+            }
+            ',' if brace_depth > 0 => re.push('|'),
+            '}' if brace_depth > 0 => {
+                brace_depth -= 1;
+                re.push(')');
+            }
+            c => re.push_str(regex::escape(c.to_string().as_str()).as_str()),
+        }
+    }
+    re.push('$');
+    Regex::new(re.as_str())
+        .map_err(|e| JsError::new_string(format!("invalid glob pattern {pattern}: {e}")))
+}
+
+fn is_glob_segment(segment: &str) -> bool {
+    segment.contains(['*', '?', '[', '{'])
+}
+
+/// split a glob pattern in a root dir to walk and a pattern to match relative to that root
+/// e.g. src/**/*.ts becomes (src, **/*.ts)
+pub(crate) fn split_glob(pattern: &str) -> (String, String) {
+    let segments: Vec<&str> = pattern.split('/').collect();
+    let idx = segments
+        .iter()
+        .position(|s| is_glob_segment(s))
+        .unwrap_or(segments.len() - 1);
+    let root = match idx {
+        // an absolute path where the first segment is a glob
+        1 if segments[0].is_empty() => "/".to_string(),
+        _ => segments[..idx].join("/"),
+    };
+    (root, segments[idx..].join("/"))
+}
+
+pub(crate) struct WalkEntry {
+    path: String,
+    name: String,
+    depth: usize,
+    stats: FileStats,
+}
+
+impl WalkEntry {
+    pub(crate) fn to_js_value(
+        &self,
+        realm: &QuickJsRealmAdapter,
+    ) -> Result<QuickJsValueAdapter, JsError> {
+        let obj = realm.create_object()?;
+        realm.set_object_property(&obj, "path", &realm.create_string(self.path.as_str())?)?;
+        realm.set_object_property(&obj, "name", &realm.create_string(self.name.as_str())?)?;
+        realm.set_object_property(&obj, "depth", &realm.create_i32(self.depth as i32)?)?;
+        realm.set_object_property(&obj, "metadata", &self.stats.to_js_value(realm)?)?;
+        Ok(obj)
+    }
+}
+
+pub(crate) struct Walker {
+    // the root as passed from script, used to build the paths of the entries
+    root: String,
+    real_root: Option<PathBuf>,
+    options: WalkOptions,
+    permissions: Arc<FsPermissions>,
+    // relative paths and depth of entries which were listed but not returned yet
+    pending: VecDeque<(PathBuf, usize)>,
+    // dirs which were descended into when following symlinks, so we don't loop
+    visited: HashSet<PathBuf>,
+}
+
+impl Walker {
+    pub(crate) fn new(root: String, options: WalkOptions, permissions: Arc<FsPermissions>) -> Self {
+        Self {
+            root,
+            real_root: None,
+            options,
+            permissions,
+            pending: VecDeque::new(),
+            visited: HashSet::new(),
+        }
+    }
+
+    fn entry_path(&self, rel: &str) -> String {
+        if self.root.is_empty() {
+            rel.to_string()
+        } else if self.root.ends_with('/') {
+            format!("{}{rel}", self.root)
+        } else {
+            format!("{}/{rel}", self.root)
+        }
+    }
+
+    fn may_descend(&self, depth: usize) -> bool {
+        self.options
+            .max_depth
+            .map(|max| depth < max)
+            .unwrap_or(true)
+    }
+
+    fn read_dir(&mut self, rel: PathBuf, depth: usize) -> std::io::Result<()> {
+        let real_root = self.real_root.as_ref().expect("walker was not started");
+        let mut names = vec![];
+        for entry in fs::read_dir(real_root.join(&rel))? {
+            names.push(entry?.file_name());
+        }
+        names.sort();
+        for name in names.into_iter().rev() {
+            self.pending.push_front((rel.join(name), depth + 1));
+        }
+        Ok(())
+    }
+
+    fn start(&mut self) -> Result<(), FsError> {
+        let root = if self.root.is_empty() {
+            "."
+        } else {
+            self.root.as_str()
+        };
+        let real_root = self
+            .permissions
+            .check(root, Access::Read, true, "scandir")?;
+        if self.options.follow_symlinks {
+            if let Ok(canonical) = real_root.canonicalize() {
+                self.visited.insert(canonical);
+            }
+        }
+        self.real_root = Some(real_root);
+        if self.may_descend(0) {
+            let root = self.root.clone();
+            self.read_dir(PathBuf::new(), 0)
+                .map_err(|e| FsError::from_io(e, "scandir", root.as_str()))?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn next_entry(&mut self) -> Result<Option<WalkEntry>, FsError> {
+        if self.real_root.is_none() {
+            self.start()?;
+        }
+        let real_root = self.real_root.clone().expect("walker was not started");
+
+        while let Some((rel, depth)) = self.pending.pop_front() {
+            let real = real_root.join(&rel);
+            let rel_str = rel.to_string_lossy().replace('\\', "/");
+
+            if self.options.exclude.iter().any(|re| re.is_match(&rel_str)) {
+                continue;
+            }
+
+            let meta = if self.options.follow_symlinks {
+                // a link may point to outside of the allowed roots
+                if self
+                    .permissions
+                    .check(real.to_string_lossy().as_ref(), Access::Read, true, "stat")
+                    .is_err()
+                {
+                    continue;
+                }
+                fs::metadata(&real).or_else(|_| fs::symlink_metadata(&real))
+            } else {
+                fs::symlink_metadata(&real)
+            };
+            let meta = match meta {
+                Ok(meta) => meta,
+                // removed while we were walking
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(FsError::from_io(e, "lstat", &self.entry_path(&rel_str))),
+            };
+
+            if meta.is_dir() && self.may_descend(depth) {
+                let descend = !self.options.follow_symlinks
+                    || real
+                        .canonicalize()
+                        .map(|canonical| self.visited.insert(canonical))
+                        .unwrap_or(false);
+                if descend {
+                    if let Err(e) = self.read_dir(rel.clone(), depth) {
+                        log::debug!("walk could not read dir {}: {}", real.display(), e);
+                    }
+                }
+            }
+
+            if self.options.include.is_empty()
+                || self.options.include.iter().any(|re| re.is_match(&rel_str))
+            {
+                let name = rel
+                    .file_name()
+                    .map(|n| n.to_string_lossy().to_string())
+                    .unwrap_or_default();
+                return Ok(Some(WalkEntry {
+                    path: self.entry_path(&rel_str),
+                    name,
+                    depth,
+                    stats: FileStats::from(&meta),
+                }));
+            }
+        }
+        Ok(None)
+    }
+}
+
+pub(crate) fn store_walker(walker: Walker) -> usize {
+    WALKERS.with(|rc| {
+        let map = &mut *rc.borrow_mut();
+        map.insert(Arc::new(Mutex::new(walker)))
+    })
+}
+
+fn with_walker(instance_id: &usize) -> Result<Arc<Mutex<Walker>>, JsError> {
+    WALKERS.with(|rc| {
+        let map = &*rc.borrow();
+        map.get(instance_id)
+            .cloned()
+            .ok_or_else(|| JsError::new_str("no such FsWalker"))
+    })
+}
+
+/// get the next entry in a helper thread and resolve to an iterator result {done, value}
+fn walker_next(
+    realm: &QuickJsRealmAdapter,
+    walker: Arc<Mutex<Walker>>,
+) -> Result<QuickJsValueAdapter, JsError> {
+    fs_promise(
+        realm,
+        move || walker.lock().unwrap().next_entry(),
+        |realm, entry| {
+            let res = realm.create_object()?;
+            match entry {
+                None => {
+                    realm.set_object_property(&res, "done", &realm.create_boolean(true)?)?;
+                }
+                Some(entry) => {
+                    realm.set_object_property(&res, "done", &realm.create_boolean(false)?)?;
+                    realm.set_object_property(&res, "value", &entry.to_js_value(realm)?)?;
+                }
+            }
+            Ok(res)
+        },
+    )
+}
+
+pub(crate) fn create_walker_proxy(_realm: &QuickJsRealmAdapter) -> JsProxy {
+    JsProxy::new()
+        .namespace(&["greco", "io", "fs"])
+        .name("FsWalker")
+        .method("next", |_rt, realm, instance_id, _args| {
+            walker_next(realm, with_walker(instance_id)?)
+        })
+        .method("Symbol.asyncIterator", |_rt, realm, instance_id, _args| {
+            // return an object with a next func which resolves to {done: false|true, value: null | nextVal}
+            let obj = realm.create_object()?;
+            let walker = with_walker(instance_id)?;
+            let next_func = realm.create_function(
+                "next",
+                move |realm, _this, _args| walker_next(realm, walker.clone()),
+                0,
+            )?;
+            realm.set_object_property(&obj, "next", &next_func)?;
+            Ok(obj)
+        })
+        .finalizer(|_rt, _realm, instance_id| {
+            WALKERS.with(|rc| {
+                let map = &mut *rc.borrow_mut();
+                let _ = map.remove(&instance_id);
+            })
+        })
+}
@@ -0,0 +1,209 @@
+//! FsWatcher proxy
+//!
+//! an FsWatcher is obtained by calling watch() in the greco://fs module, it dispatches events when
+//! files in the watched path are created, modified, removed or renamed
+//!
+//! on linux the watcher is backed by inotify
+//!
+//! # Example
+//!
+//! ```javascript
+//! async function test() {
+//!     let fs = await import('greco://fs');
+//!     let watcher = fs.watch('./import', {recursive: true});
+//!     watcher.addEventListener('create', (evt) => {
+//!         console.log("file created: %s", evt.path);
+//!     });
+//!     watcher.addEventListener('rename', (evt) => {
+//!         // paths contains both the old and the new path if both are known
+//!         console.log("file renamed: %s", evt.paths.join(' -> '));
+//!     });
+//!     // stop watching
+//!     // watcher.close();
+//! }
+//! ```
+//!
+//! # Events
+//!
+//! all events are objects like {path: string, paths: Array<string>}
+//!
+//! ##create
+//! ##modify
+//! ##remove
+//! ##rename
+//!
+//! # Methods
+//!
+//! ##close(): void
+//! stop watching, the watcher is also closed when it is garbage collected so keep a reference to it
+//! for as long as you want to receive events
+//!
+
+use crate::modules::io::fs::permissions::{Access, FsPermissions};
+use crate::modules::io::fs::{bool_option, FsError};
+use notify::event::ModifyKind;
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use quickjs_runtime::jsutils::jsproxies::JsProxy;
+use quickjs_runtime::jsutils::JsError;
+use quickjs_runtime::quickjsrealmadapter::QuickJsRealmAdapter;
+use quickjs_runtime::quickjsvalueadapter::QuickJsValueAdapter;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+thread_local! {
+    static WATCHERS: RefCell<HashMap<usize, RecommendedWatcher>> = RefCell::new(HashMap::new());
+}
+
+fn event_type(kind: &EventKind) -> Option<&'static str> {
+    match kind {
+        EventKind::Create(_) => Some("create"),
+        EventKind::Modify(ModifyKind::Name(_)) => Some("rename"),
+        EventKind::Modify(_) => Some("modify"),
+        EventKind::Remove(_) => Some("remove"),
+        _ => None,
+    }
+}
+
+fn dispatch_event(
+    realm: &QuickJsRealmAdapter,
+    instance_id: usize,
+    evt: Event,
+) -> Result<(), JsError> {
+    if let Some(evt_type) = event_type(&evt.kind) {
+        let evt_obj = realm.create_object()?;
+        let paths = realm.create_array()?;
+        for path in &evt.paths {
+            let path_ref = realm.create_string(path.to_string_lossy().as_ref())?;
+            realm.push_array_element(&paths, &path_ref)?;
+        }
+        if let Some(path) = evt.paths.first() {
+            let path_ref = realm.create_string(path.to_string_lossy().as_ref())?;
+            realm.set_object_property(&evt_obj, "path", &path_ref)?;
+        }
+        realm.set_object_property(&evt_obj, "paths", &paths)?;
+        realm.dispatch_proxy_event(
+            &["greco", "io", "fs"],
+            "FsWatcher",
+            &instance_id,
+            evt_type,
+            &evt_obj,
+        )?;
+    }
+    Ok(())
+}
+
+/// a notify error as an FsError so its message starts with an errno style code like the other fs
+/// errors
+fn watch_error(err: notify::Error, path: &str) -> FsError {
+    match err.kind {
+        notify::ErrorKind::Io(io_err) => FsError::from_io(io_err, "watch", path),
+        notify::ErrorKind::PathNotFound => FsError::from_io(
+            std::io::Error::from(std::io::ErrorKind::NotFound),
+            "watch",
+            path,
+        ),
+        kind => FsError::new("EIO", format!("EIO: {kind:?}, watch '{path}'")),
+    }
+}
+
+/// ids of the FsWatcher instances, those are only created by watch()
+static NEXT_WATCHER_ID: AtomicUsize = AtomicUsize::new(1);
+
+/// the native part of watch(), the outer Err is thrown as is, an FsError is returned so the caller
+/// can throw it with its code
+pub(crate) fn start_watcher(
+    permissions: &FsPermissions,
+    realm: &QuickJsRealmAdapter,
+    args: &[QuickJsValueAdapter],
+) -> Result<Result<QuickJsValueAdapter, FsError>, JsError> {
+    if args.is_empty() || !args[0].is_string() {
+        return Err(JsError::new_str(
+            "watch requires one or two arguments: (String, {recursive: boolean}?)",
+        ));
+    }
+    let path = args[0].to_string()?;
+    let recursive = bool_option(realm, args.get(1), "recursive")?;
+
+    let instance_id = NEXT_WATCHER_ID.fetch_add(1, Ordering::SeqCst);
+    let watcher = match create_watcher(permissions, realm, instance_id, &path, recursive) {
+        Ok(watcher) => watcher,
+        Err(e) => return Ok(Err(e)),
+    };
+    WATCHERS.with(|rc| {
+        let watchers = &mut *rc.borrow_mut();
+        watchers.insert(instance_id, watcher);
+    });
+    realm
+        .instantiate_proxy_with_id(&["greco", "io", "fs"], "FsWatcher", instance_id)
+        .map(Ok)
+}
+
+fn create_watcher(
+    permissions: &FsPermissions,
+    realm: &QuickJsRealmAdapter,
+    instance_id: usize,
+    path: &str,
+    recursive: bool,
+) -> Result<RecommendedWatcher, FsError> {
+    let real = permissions.check(path, Access::Read, true, "watch")?;
+
+    let rti_ref = realm.get_runtime_facade_inner();
+    let realm_id = realm.get_realm_id().to_string();
+
+    let mut watcher = notify::recommended_watcher(move |res: notify::Result<Event>| {
+        // in the watcher thread here
+        match res {
+            Ok(evt) => {
+                log::trace!("FsWatcher {} got event {:?}", instance_id, evt);
+                if let Some(rt_ref) = rti_ref.upgrade() {
+                    let realm_id = realm_id.clone();
+                    rt_ref.add_rt_task_to_event_loop_void(move |runtime| {
+                        if let Some(realm) = runtime.get_realm(realm_id.as_str()) {
+                            if let Err(e) = dispatch_event(realm, instance_id, evt) {
+                                log::error!("FsWatcher event dispatch failed: {}", e);
+                            }
+                        } else {
+                            log::error!("realm not found");
+                        }
+                    });
+                }
+            }
+            Err(e) => {
+                log::error!("FsWatcher {} failed: {}", instance_id, e);
+            }
+        }
+    })
+    .map_err(|e| watch_error(e, path))?;
+
+    let mode = if recursive {
+        RecursiveMode::Recursive
+    } else {
+        RecursiveMode::NonRecursive
+    };
+    watcher
+        .watch(real.as_path(), mode)
+        .map_err(|e| watch_error(e, path))?;
+    Ok(watcher)
+}
+
+pub(crate) fn create_watcher_proxy() -> JsProxy {
+    JsProxy::new()
+        .namespace(&["greco", "io", "fs"])
+        .name("FsWatcher")
+        .event_target()
+        .method("close", |_runtime, realm, instance_id, _args| {
+            // dropping the watcher stops it
+            WATCHERS.with(|rc| {
+                let watchers = &mut *rc.borrow_mut();
+                let _ = watchers.remove(instance_id);
+            });
+            realm.create_null()
+        })
+        .finalizer(|_runtime, _realm, instance_id| {
+            WATCHERS.with(|rc| {
+                let watchers = &mut *rc.borrow_mut();
+                let _ = watchers.remove(&instance_id);
+            })
+        })
+}